@@ -1,8 +1,13 @@
 use crate::cartridge::{Cart, ROM_START, ROM_STOP, EXT_RAM_START, EXT_RAM_STOP};
+use crate::cpu::{Interrupts, IF_REG};
+use crate::cpu::memory_bus::MemoryBus;
+use crate::cpu::timer::{Timer, DIV, TAC};
 use crate::io::{Buttons, IO};
-use crate::ppu::{PPU, LY, VBK, PpuUpdateResult};
-use crate::ppu::mode::LcdModeType;
+use crate::ppu::{PPU, VBK};
+use crate::ppu::mode::{LcdModeType, LcdResults, FRAME_LEN};
 use crate::ppu::palette::Palettes;
+use crate::serial::{Serial, SerialLink, SB, SC};
+use crate::sgb::SGB;
 use crate::utils::*;
 use crate::wram::{WRAM, WRAM_START, WRAM_END, SVBK_REG, ECHO_START, ECHO_END};
 
@@ -59,25 +64,126 @@ use std::cmp::min;
 // =============
 const JOYPAD_REG: u16       = 0xFF00;
 const DMA_REG: u16          = 0xFF46;
+const KEY1_REG: u16         = 0xFF4D;   // CGB Speed Switch
+const BOOT_REG: u16         = 0xFF50;   // Boot ROM disable
 const HDMA1_REG: u16        = 0xFF51;
 const HDMA2_REG: u16        = 0xFF52;
 const HDMA3_REG: u16        = 0xFF53;
 const HDMA4_REG: u16        = 0xFF54;
 const HDMA5_REG: u16        = 0xFF55;
 
+const VRAM_START: u16 = 0x8000;
+const VRAM_END: u16 = 0x9FFF;
 const OAM: u16 = 0xFE00;
 const HRAM_START: u16 = 0xFF80;
 const HRAM_END: u16 = 0xFFFF; // Include $FFFF as part of HRAM
 const HRAM_SIZE: usize = (HRAM_END - HRAM_START + 1) as usize;
 const VRAM_DMA_PER_HBLANK: u16 = 0x10;
+const VRAM_DMA_BLOCK_CYCLES: u16 = 8; // Normal speed machine cycles to copy one $10 byte block
+const OAM_DMA_LEN: u16 = 0xA0;
+const OAM_DMA_STARTUP: u8 = 1;
+const SPEED_SWITCH_CYCLES: u16 = 2050; // Machine cycles the CPU is paused for while switching speeds
+const DMG_BOOT_ROM_SIZE: usize = 0x100;
+const CGB_BOOT_ROM_SIZE: usize = 0x900;
+const BOOT_ROM_HEADER_START: u16 = 0x0100; // The cartridge header is always visible, even on CGB
+const BOOT_ROM_HEADER_END: u16 = 0x01FF;
+const KEYPAD_PAL_FRAMES: u8 = 120; // Roughly how long the CGB boot ROM's logo is shown, while it checks the keypad
+
+// Internal divider counter once each model's boot ROM hands over to the game
+const DMG0_POST_BOOT_DIV: u16 = 0x1830;
+const DMG_POST_BOOT_DIV: u16 = 0xABCC;
+const SGB_POST_BOOT_DIV: u16 = DMG_POST_BOOT_DIV; // Depends on SNES timing, so it isn't consistent
+const CGB_POST_BOOT_DIV: u16 = 0x1EA0;
+const CGB_COMPAT_POST_BOOT_DIV: u16 = 0x267C; // Setting up compatibility palettes takes the CGB boot ROM longer
+
+// I/O registers as each model's boot ROM leaves them, from pandocs
+// Columns are DMG0, DMG/MGB, SGB/SGB2 and CGB/AGB
+// DIV, LY, STAT, DMA, HDMA5 and SVBK follow from other hardware state, so aren't set directly
+const POST_BOOT_IO: [(u16, [u8; 4]); 43] = [
+    (0xFF01, [0x00, 0x00, 0x00, 0x00]), // SB
+    (0xFF02, [0x7E, 0x7E, 0x7E, 0x7F]), // SC
+    (0xFF05, [0x00, 0x00, 0x00, 0x00]), // TIMA
+    (0xFF06, [0x00, 0x00, 0x00, 0x00]), // TMA
+    (0xFF07, [0xF8, 0xF8, 0xF8, 0xF8]), // TAC
+    (0xFF0F, [0xE1, 0xE1, 0xE1, 0xE1]), // IF
+    (0xFF10, [0x80, 0x80, 0x80, 0x80]), // NR10
+    (0xFF11, [0xBF, 0xBF, 0xBF, 0xBF]), // NR11
+    (0xFF12, [0xF3, 0xF3, 0xF3, 0xF3]), // NR12
+    (0xFF13, [0xFF, 0xFF, 0xFF, 0xFF]), // NR13
+    (0xFF14, [0xBF, 0xBF, 0xBF, 0xBF]), // NR14
+    (0xFF16, [0x3F, 0x3F, 0x3F, 0x3F]), // NR21
+    (0xFF17, [0x00, 0x00, 0x00, 0x00]), // NR22
+    (0xFF18, [0xFF, 0xFF, 0xFF, 0xFF]), // NR23
+    (0xFF19, [0xBF, 0xBF, 0xBF, 0xBF]), // NR24
+    (0xFF1A, [0x7F, 0x7F, 0x7F, 0x7F]), // NR30
+    (0xFF1B, [0xFF, 0xFF, 0xFF, 0xFF]), // NR31
+    (0xFF1C, [0x9F, 0x9F, 0x9F, 0x9F]), // NR32
+    (0xFF1D, [0xFF, 0xFF, 0xFF, 0xFF]), // NR33
+    (0xFF1E, [0xBF, 0xBF, 0xBF, 0xBF]), // NR34
+    (0xFF20, [0xFF, 0xFF, 0xFF, 0xFF]), // NR41
+    (0xFF21, [0x00, 0x00, 0x00, 0x00]), // NR42
+    (0xFF22, [0x00, 0x00, 0x00, 0x00]), // NR43
+    (0xFF23, [0xBF, 0xBF, 0xBF, 0xBF]), // NR44
+    (0xFF24, [0x77, 0x77, 0x77, 0x77]), // NR50
+    (0xFF25, [0xF3, 0xF3, 0xF3, 0xF3]), // NR51
+    (0xFF26, [0xF1, 0xF1, 0xF0, 0xF1]), // NR52
+    (0xFF40, [0x91, 0x91, 0x91, 0x91]), // LCDC
+    (0xFF42, [0x00, 0x00, 0x00, 0x00]), // SCY
+    (0xFF43, [0x00, 0x00, 0x00, 0x00]), // SCX
+    (0xFF45, [0x00, 0x00, 0x00, 0x00]), // LYC
+    (0xFF47, [0xFC, 0xFC, 0xFC, 0xFC]), // BGP
+    (0xFF48, [0xFF, 0xFF, 0xFF, 0xFF]), // OBP0
+    (0xFF49, [0xFF, 0xFF, 0xFF, 0xFF]), // OBP1
+    (0xFF4A, [0x00, 0x00, 0x00, 0x00]), // WY
+    (0xFF4B, [0x00, 0x00, 0x00, 0x00]), // WX
+    (0xFF4D, [0xFF, 0xFF, 0xFF, 0x7E]), // KEY1
+    (0xFF4F, [0xFF, 0xFF, 0xFF, 0xFE]), // VBK
+    (0xFF51, [0xFF, 0xFF, 0xFF, 0xFF]), // HDMA1
+    (0xFF52, [0xFF, 0xFF, 0xFF, 0xFF]), // HDMA2
+    (0xFF53, [0xFF, 0xFF, 0xFF, 0xFF]), // HDMA3
+    (0xFF54, [0xFF, 0xFF, 0xFF, 0xFF]), // HDMA4
+    (0xFF56, [0xFF, 0xFF, 0xFF, 0x3E]), // RP
+];
+// The DMG0 boot ROM finishes partway through VBLANK, while later models finish around the top of the frame
+const DMG0_POST_BOOT_LY: u8 = 0x91;
 
 pub struct Bus {
     rom: Cart,
     io: IO,
+    serial: Serial,
+    sgb: SGB,
     ppu: PPU,
     wram: WRAM,
     hram: [u8; HRAM_SIZE],
     vram_dma_remaining: Option<VRAM_DMA>,
+    vram_dma_stall: u16,
+    oam_dma_remaining: Option<OAM_DMA>,
+    dma_reg: u8,
+    mode: GB,
+    timer: Timer,
+    double_speed: bool,
+    speed_switch_armed: bool,
+    speed_switch_delay: u16,
+    dma_stall: u16,
+    cpu_halted: bool,
+    stopped_dots: usize,
+    elapsed_dots: u64,
+    frame_ready: bool,
+    dirty_battery_ram: bool,
+    boot_rom: Option<Vec<u8>>,
+    boot_rom_mapped: bool,
+    game_mode: GB,
+    keypad_pal_frames: u8,
+    requested_model: Option<Model>,
+    model: Model,
+}
+
+#[allow(non_camel_case_types)]
+#[derive(Copy, Clone)]
+struct OAM_DMA {
+    pub src_addr: u16,
+    pub transferred: u16,
+    pub delay: u8,
 }
 
 #[allow(non_camel_case_types)]
@@ -87,7 +193,6 @@ struct VRAM_DMA {
     pub dst_addr: u16,
     pub len: u16,
     pub transferred: u16,
-    pub last_scanline: u8,
     pub active: bool,
 }
 
@@ -105,10 +210,32 @@ impl Bus {
         Bus {
             rom: Cart::new(),
             io: IO::new(),
+            serial: Serial::new(),
+            sgb: SGB::new(),
             ppu: PPU::new(),
             wram: WRAM::new(),
             hram: [0; HRAM_SIZE],
             vram_dma_remaining: None,
+            vram_dma_stall: 0,
+            oam_dma_remaining: None,
+            dma_reg: 0xFF,
+            mode: GB::DMG,
+            timer: Timer::new(),
+            double_speed: false,
+            speed_switch_armed: false,
+            speed_switch_delay: 0,
+            dma_stall: 0,
+            cpu_halted: false,
+            stopped_dots: 0,
+            elapsed_dots: 0,
+            frame_ready: false,
+            dirty_battery_ram: false,
+            boot_rom: None,
+            boot_rom_mapped: false,
+            game_mode: GB::DMG,
+            keypad_pal_frames: 0,
+            requested_model: None,
+            model: Model::DMG,
         }
     }
 
@@ -117,14 +244,175 @@ impl Bus {
     ///
     /// Loads game into ROM
     ///
-    /// Input:
+    /// Inputs:
     ///     ROM data (&[u8])
+    ///     Whether to force DMG mode (bool)
+    ///
+    /// Output:
+    ///     Which type of system the game will run as
+    /// ```
+    pub fn load_game(&mut self, rom: &[u8], force_dmg: bool) -> GB {
+        let cart_mode = self.rom.load_cart(rom);
+        let cgb_boot_rom = self.boot_rom.as_ref().is_some_and(|boot_rom| boot_rom.len() == CGB_BOOT_ROM_SIZE);
+
+        // Without a requested model, pick the oldest hardware able to run the game
+        self.model = if force_dmg {
+            Model::DMG
+        } else if let Some(model) = self.requested_model {
+            model
+        } else if cart_mode == GB::CGB || cgb_boot_rom {
+            Model::CGB
+        } else {
+            Model::DMG
+        };
+
+        // DMG games run in compatibility mode on CGB hardware, and CGB games can only run in DMG mode on older hardware
+        self.game_mode = if self.model.is_cgb() {
+            cart_mode
+        } else if self.model.is_sgb() {
+            GB::SGB
+        } else {
+            GB::DMG
+        };
+        self.mode = self.game_mode;
+
+        // A CGB boot ROM runs in CGB mode for any game, switching to the game's mode once finished
+        // Boot ROMs only run on the hardware they were made for
+        self.boot_rom_mapped = match &self.boot_rom {
+            Some(_) if cgb_boot_rom => { self.model.is_cgb() },
+            Some(_) => { !self.model.is_cgb() },
+            None => { false }
+        };
+        if self.boot_rom_mapped && self.model.is_cgb() {
+            self.mode = GB::CGB;
+        } else if !self.boot_rom_mapped {
+            // Without a boot ROM, a keypad palette can still be picked for a short while
+            self.set_compat_pal();
+            self.keypad_pal_frames = KEYPAD_PAL_FRAMES;
+        }
+
+        self.game_mode
+    }
+
+    /// ```
+    /// Set model
+    ///
+    /// Chooses which hardware model to emulate for the next loaded game
+    ///
+    /// Input:
+    ///     Model, or None to choose based on the game (Option<Model>)
+    /// ```
+    pub fn set_model(&mut self, model: Option<Model>) {
+        self.requested_model = model;
+    }
+
+    /// ```
+    /// Get model
+    ///
+    /// Returns which hardware model is being emulated
+    ///
+    /// Output:
+    ///     Hardware model (Model)
+    /// ```
+    pub fn get_model(&self) -> Model {
+        self.model
+    }
+
+    /// ```
+    /// Post boot
+    ///
+    /// Sets up the I/O registers as the boot ROM of the current model leaves them,
+    /// for when the game is started without running it
+    /// ```
+    pub fn post_boot(&mut self) {
+        let (column, div) = match self.model {
+            Model::DMG0 =>                          { (0, DMG0_POST_BOOT_DIV) },
+            Model::DMG | Model::MGB =>              { (1, DMG_POST_BOOT_DIV) },
+            Model::SGB | Model::SGB2 =>             { (2, SGB_POST_BOOT_DIV) },
+            Model::CGB | Model::AGB => {
+                (3, if self.game_mode == GB::CGB { CGB_POST_BOOT_DIV } else { CGB_COMPAT_POST_BOOT_DIV })
+            }
+        };
+
+        for (addr, vals) in POST_BOOT_IO {
+            self.write_ram(addr, vals[column]);
+        }
+        self.timer.set_div_counter(div);
+
+        if self.model == Model::DMG0 {
+            self.ppu.skip_to_vblank_line(DMG0_POST_BOOT_LY);
+        }
+
+        self.dma_reg = if self.model.is_cgb() { 0x00 } else { 0xFF };
+    }
+
+    /// ```
+    /// Get title checksum
+    ///
+    /// Gets the sum of the game title's bytes, as calculated by the CGB boot ROM
+    ///
+    /// Output:
+    ///     Title checksum, or None if not a Nintendo game (Option<u8>)
+    /// ```
+    pub fn get_title_checksum(&self) -> Option<u8> {
+        self.rom.get_title_checksum()
+    }
+
+    /// ```
+    /// Get header checksum
+    ///
+    /// Gets the checksum byte of the cartridge header
     ///
     /// Output:
-    ///     Which type of system this cart requires
+    ///     Header checksum (u8)
     /// ```
-    pub fn load_game(&mut self, rom: &[u8]) -> GB {
-        self.rom.load_cart(&rom)
+    pub fn get_header_checksum(&self) -> u8 {
+        self.rom.get_header_checksum()
+    }
+
+    /// ```
+    /// Load boot ROM
+    ///
+    /// Stores a boot ROM, to be mapped in when the next game is loaded
+    /// DMG and MGB boot ROMs are 256 bytes, CGB boot ROMs are 2304 bytes
+    ///
+    /// Input:
+    ///     Boot ROM data (&[u8])
+    ///
+    /// Output:
+    ///     Whether the data is a valid boot ROM (bool)
+    /// ```
+    pub fn load_boot_rom(&mut self, data: &[u8]) -> bool {
+        if data.len() != DMG_BOOT_ROM_SIZE && data.len() != CGB_BOOT_ROM_SIZE {
+            return false;
+        }
+
+        self.boot_rom = Some(data.to_vec());
+        true
+    }
+
+    /// ```
+    /// Is boot ROM mapped
+    ///
+    /// Whether the boot ROM is currently running, overlaying the cartridge ROM
+    ///
+    /// Output:
+    ///     Whether the boot ROM is mapped (bool)
+    /// ```
+    pub fn is_boot_rom_mapped(&self) -> bool {
+        self.boot_rom_mapped
+    }
+
+    /// ```
+    /// Get mode
+    ///
+    /// Returns which type of system is being emulated
+    ///
+    /// Output:
+    ///     GB hardware type (GB)
+    /// ```
+    pub fn get_mode(&self) -> GB {
+        self.mode
     }
 
     /// ```
@@ -136,7 +424,23 @@ impl Bus {
     ///     Array of pixels to draw ([u8])
     /// ```
     pub fn render(&self) -> [u8; DISP_SIZE] {
-        self.ppu.render_screen()
+        if self.mode == GB::SGB {
+            self.sgb.render_screen()
+        } else {
+            self.ppu.render_screen()
+        }
+    }
+
+    /// ```
+    /// Render SGB
+    ///
+    /// Renders the screen within the Super Game Boy border
+    ///
+    /// Output:
+    ///     Array of SGB_SCREEN_WIDTH x SGB_SCREEN_HEIGHT pixels to draw ([u8])
+    /// ```
+    pub fn render_sgb(&self) -> [u8; SGB_DISP_SIZE] {
+        self.sgb.render_border(&self.render())
     }
 
     /// ```
@@ -147,12 +451,16 @@ impl Bus {
     /// Input:
     ///     RAM address (u16)
     ///     Bank override (Option<u16>)
-    ///     System mode (GB)
     ///
     /// Output:
     ///     Value at address (u8)
     /// ```
-    pub fn read_ram(&self, addr: u16, bank_override: Option<u16>, mode: GB) -> u8 {
+    pub fn read_ram(&self, addr: u16, bank_override: Option<u16>) -> u8 {
+        if let Some(byte) = self.read_boot_rom(addr) {
+            return byte;
+        }
+
+        let mode = self.mode;
         match addr {
             ROM_START..=ROM_STOP | EXT_RAM_START..=EXT_RAM_STOP => {
                 self.rom.read_cart(addr, bank_override)
@@ -164,10 +472,34 @@ impl Bus {
                 self.wram.read_echo(addr, bank_override)
             },
             JOYPAD_REG => {
-                self.io.read_btns()
+                if mode == GB::SGB {
+                    self.sgb.read_joypad(self.io.read_btns())
+                } else {
+                    self.io.read_btns()
+                }
+            },
+            SB | SC => {
+                self.serial.read_serial(addr, mode)
+            },
+            DIV..=TAC => {
+                self.timer.read_timer(addr)
+            },
+            BOOT_REG => {
+                0xFF
+            },
+            KEY1_REG if mode == GB::CGB => {
+                // Bit 7 is the current speed, bit 0 is whether a switch is armed. Unused bits read high
+                let mut key1 = 0x7E;
+                key1.write_bit(7, self.double_speed);
+                key1.write_bit(0, self.speed_switch_armed);
+                key1
+            },
+            DMA_REG => {
+                self.dma_reg
             },
             SVBK_REG => {
-                self.wram.get_wram_bank()
+                // Only exists on CGB, otherwise it's open bus
+                if mode == GB::CGB { self.wram.get_wram_bank() } else { 0xFF }
             },
             HDMA5_REG => {
                 match self.vram_dma_remaining {
@@ -203,17 +535,13 @@ impl Bus {
     /// Input:
     ///     RAM address (u16)
     ///     Value to write (u8)
-    ///     System mode (GB)
-    ///
-    /// Output:
-    ///     Whether data was written to battery-saved RAM
     /// ```
-    pub fn write_ram(&mut self, addr: u16, val: u8, mode: GB) -> bool {
-        let mut battery_write = false;
+    pub fn write_ram(&mut self, addr: u16, val: u8) {
+        let mode = self.mode;
         match addr {
             ROM_START..=ROM_STOP | EXT_RAM_START..=EXT_RAM_STOP => {
                 self.rom.write_cart(addr, val);
-                battery_write = true;
+                self.dirty_battery_ram = true;
             },
             WRAM_START..=WRAM_END => {
                 self.wram.write_wram(addr, val);
@@ -224,13 +552,34 @@ impl Bus {
             },
             JOYPAD_REG => {
                 self.io.poll_btns(val);
+                if mode == GB::SGB && self.rom.supports_sgb() {
+                    self.sgb.write_joypad(val);
+                }
+            },
+            SB | SC => {
+                self.serial.write_serial(addr, val, mode);
+            },
+            DIV..=TAC => {
+                self.timer.write_timer(addr, val);
+            },
+            BOOT_REG => {
+                // Once unmapped, the boot ROM can't be mapped back in until power off
+                if self.boot_rom_mapped && val.get_bit(0) {
+                    self.boot_rom_mapped = false;
+                    self.mode = self.game_mode;
+                    self.set_compat_pal();
+                }
+            },
+            KEY1_REG if mode == GB::CGB => {
+                // Only arms the switch, which actually occurs on the next STOP instruction
+                self.speed_switch_armed = val.get_bit(0);
             },
             DMA_REG => {
-                self.oam_dma(val, mode);
+                self.oam_dma(val);
             },
             HDMA5_REG => {
                 if mode == GB::CGB {
-                    self.vram_dma(Some(val));
+                    self.vram_dma(val);
                 } else {
                     self.ppu.write_vram(addr, val, mode);
                 }
@@ -251,8 +600,6 @@ impl Bus {
                 self.ppu.write_vram(addr, val, mode);
             }
         }
-
-        battery_write
     }
 
     /// ```
@@ -266,6 +613,21 @@ impl Bus {
     /// ```
     pub fn toggle_button(&mut self, btn: Buttons, pressed: bool) {
         self.io.btn_toggle(btn, pressed);
+        if pressed && self.keypad_pal_frames > 0 {
+            self.set_keypad_pal();
+        }
+    }
+
+    /// ```
+    /// Is joypad line low
+    ///
+    /// Whether a button in the currently selected group is pressed
+    ///
+    /// Output:
+    ///     Whether any joypad input line is low (bool)
+    /// ```
+    pub fn is_joypad_line_low(&self) -> bool {
+        self.io.is_line_low()
     }
 
     /// ```
@@ -306,25 +668,165 @@ impl Bus {
         raw_title.trim_end_matches(char::from(0))
     }
 
-    pub fn update_ppu(&mut self, cycles: u8, gb_mode: GB) -> PpuUpdateResult {
-        let ret = self.ppu.update(cycles);
-        if self.ppu.get_lcd_mode() == LcdModeType::HBLANK && gb_mode == GB::CGB {
-            self.vram_dma(None);
+    /// ```
+    /// Set serial link
+    ///
+    /// Attaches a device to the other end of the link cable
+    ///
+    /// Input:
+    ///     Link cable backend (Box<dyn SerialLink>)
+    /// ```
+    pub fn set_serial_link(&mut self, link: Box<dyn SerialLink>) {
+        self.serial.set_link(link);
+    }
+
+    /// ```
+    /// Take serial error
+    ///
+    /// Returns the latest error from the device on the other end of the link cable, if any
+    ///
+    /// Output:
+    ///     Error since last called (Option<std::io::Error>)
+    /// ```
+    pub fn take_serial_error(&mut self) -> Option<std::io::Error> {
+        self.serial.take_link_error()
+    }
+
+    /// ```
+    /// Set serial capture
+    ///
+    /// Toggles recording of bytes sent over the serial port
+    ///
+    /// Input:
+    ///     Whether to record sent bytes (bool)
+    /// ```
+    pub fn set_serial_capture(&mut self, enabled: bool) {
+        self.serial.set_capture(enabled);
+    }
+
+    /// ```
+    /// Get serial capture
+    ///
+    /// Returns all bytes sent over the serial port since capturing was enabled
+    ///
+    /// Output:
+    ///     Sent bytes, if capturing (Option<&[u8]>)
+    /// ```
+    pub fn get_serial_capture(&self) -> Option<&[u8]> {
+        self.serial.get_capture()
+    }
+
+    /// ```
+    /// Stall cycle
+    ///
+    /// If the CPU is paused, either for a speed switch or a VRAM DMA, runs the system
+    /// for one machine cycle in its place
+    ///
+    /// Output:
+    ///     Whether the CPU is stalled (bool)
+    /// ```
+    pub fn stall_cycle(&mut self) -> bool {
+        if self.speed_switch_delay > 0 {
+            // While switching speeds, the CPU and timer are paused but the LCD keeps running
+            self.speed_switch_delay -= 1;
+            self.update_ppu(1);
+            true
+        } else if self.dma_stall > 0 {
+            // The CPU is paused while VRAM DMA copies data, but the rest of the system continues
+            self.dma_stall -= 1;
+            self.advance(1);
+            true
+        } else {
+            false
+        }
+    }
+
+    /// ```
+    /// Stopped tick
+    ///
+    /// In STOP mode, the CPU, timer and LCD are all halted until a button is pressed
+    /// Still signal a frame every so often, so frontends continue to run and poll input
+    ///
+    /// Output:
+    ///     Whether or not to render a frame (bool)
+    /// ```
+    pub fn stopped_tick(&mut self) -> bool {
+        self.stopped_dots += 4;
+        self.elapsed_dots += 4;
+        if self.stopped_dots >= FRAME_LEN {
+            self.stopped_dots -= FRAME_LEN;
+            return true;
         }
+        false
+    }
+
+    /// ```
+    /// Take frame ready
+    ///
+    /// Returns whether a frame has been completed since the last call, and clears the flag
+    ///
+    /// Output:
+    ///     Whether or not to render a frame (bool)
+    /// ```
+    pub fn take_frame_ready(&mut self) -> bool {
+        let ready = self.frame_ready;
+        self.frame_ready = false;
+        ready
+    }
 
-        ret
+    /// ```
+    /// Get elapsed dots
+    ///
+    /// Total time the system has run for, counted in dots (4.19 MHz), regardless of CPU speed
+    ///
+    /// Output:
+    ///     Number of dots elapsed since power on (u64)
+    /// ```
+    pub fn get_elapsed_dots(&self) -> u64 {
+        self.elapsed_dots
     }
 
     /// ```
-    /// Render scanline
+    /// Request interrupt
     ///
-    /// Renders the current scanline into the screen buffer
+    /// Engages the specific interrupt type
     ///
     /// Input:
-    ///     GB hardware type
+    ///     Interrupt type (Interrupts)
+    /// ```
+    pub fn request_interrupt(&mut self, inter: Interrupts) {
+        let mut if_reg = self.read_ram(IF_REG, None);
+
+        match inter {
+            Interrupts::VBLANK =>   { if_reg.set_bit(0) },
+            Interrupts::LCD_STAT => { if_reg.set_bit(1) },
+            Interrupts::TIMER =>    { if_reg.set_bit(2) },
+            Interrupts::SERIAL =>   { if_reg.set_bit(3) },
+            Interrupts::JOYPAD =>   { if_reg.set_bit(4) },
+        }
+
+        self.write_ram(IF_REG, if_reg);
+    }
+
+    /// ```
+    /// Is battery RAM dirty?
+    ///
+    /// Checks whether battery RAM has been updated
+    ///
+    /// Output:
+    ///     Whether battery RAM needs to be saved
     /// ```
-    pub fn render_scanline(&mut self, mode: GB) {
-        self.ppu.render_scanline(mode);
+    pub fn is_battery_dirty(&self) -> bool {
+        self.dirty_battery_ram
+    }
+
+    /// ```
+    /// Clean battery flag
+    ///
+    /// Resets dirty battery RAM flag
+    /// ```
+    pub fn clean_battery_flag(&mut self) {
+        self.dirty_battery_ram = false;
     }
 
     /// ```
@@ -339,6 +841,18 @@ impl Bus {
         self.ppu.set_sys_pal(pal);
     }
 
+    /// ```
+    /// Set strict VRAM access
+    ///
+    /// Toggles LCD mode based OAM/VRAM/palette access restrictions
+    ///
+    /// Input:
+    ///     Whether to enforce access restrictions (bool)
+    /// ```
+    pub fn set_strict_vram_access(&mut self, strict: bool) {
+        self.ppu.set_strict_vram_access(strict);
+    }
+
     /// ```
     /// Write external RAM
     ///
@@ -366,98 +880,316 @@ impl Bus {
 
 // Private functions
 impl Bus {
+    /// ```
+    /// Read boot ROM
+    ///
+    /// Reads from the boot ROM, if it is mapped over the given address
+    /// It covers $0000-$00FF, as well as $0200-$08FF on CGB
+    ///
+    /// Input:
+    ///     RAM address (u16)
+    ///
+    /// Output:
+    ///     Byte from the boot ROM, if mapped there (Option<u8>)
+    /// ```
+    fn read_boot_rom(&self, addr: u16) -> Option<u8> {
+        if !self.boot_rom_mapped || (BOOT_ROM_HEADER_START..=BOOT_ROM_HEADER_END).contains(&addr) {
+            return None;
+        }
+
+        self.boot_rom.as_ref()?.get(addr as usize).copied()
+    }
+
+    /// ```
+    /// Advance
+    ///
+    /// Runs the rest of the system (LCD, timer, serial and DMA) for the given number of CPU machine cycles
+    ///
+    /// Input:
+    ///     Number of CPU machine cycles which have elapsed (u8)
+    /// ```
+    fn advance(&mut self, cycles: u8) {
+        self.update_ppu(cycles);
+
+        let timer_interrupt = self.timer.tick(cycles);
+        if timer_interrupt {
+            self.request_interrupt(Interrupts::TIMER);
+        }
+
+        let serial_interrupt = self.serial.tick(cycles);
+        if serial_interrupt {
+            self.request_interrupt(Interrupts::SERIAL);
+        }
+
+        self.update_oam_dma(cycles);
+
+        // VRAM DMA takes twice as many CPU cycles in double speed mode
+        let stall = self.vram_dma_stall;
+        self.vram_dma_stall = 0;
+        self.dma_stall += if self.double_speed { 2 * stall } else { stall };
+    }
+
+    /// ```
+    /// Update PPU
+    ///
+    /// Advances the LCD by the given number of CPU machine cycles, along with any HBLANK VRAM DMA in progress
+    /// In double speed mode, the LCD runs at half the rate of the CPU
+    /// HBLANK DMA copies one block upon entering each HBLANK, unless the CPU is halted
+    ///
+    /// Input:
+    ///     Number of CPU machine cycles which have elapsed (u8)
+    /// ```
+    fn update_ppu(&mut self, cycles: u8) {
+        let dots = if self.double_speed { 2 * cycles } else { 4 * cycles };
+        self.elapsed_dots += dots as u64;
+
+        let was_hblank = self.ppu.get_lcd_mode() == LcdModeType::HBLANK;
+        let ppu_result = self.ppu.update(dots);
+        let hblank_entered = !was_hblank && self.ppu.get_lcd_mode() == LcdModeType::HBLANK;
+        if hblank_entered && self.mode == GB::CGB && !self.cpu_halted {
+            self.hblank_dma();
+        }
+
+        if ppu_result.interrupt {
+            self.request_interrupt(Interrupts::LCD_STAT);
+        }
+
+        match ppu_result.lcd_result {
+            LcdResults::RenderFrame => {
+                // If time to render frame, then VBLANK interrupt is toggled
+                self.request_interrupt(Interrupts::VBLANK);
+                self.frame_ready = true;
+                self.keypad_pal_frames = self.keypad_pal_frames.saturating_sub(1);
+                if self.mode == GB::SGB {
+                    self.sgb.update_frame(&self.ppu.render_shades());
+                }
+            },
+            LcdResults::RenderScanline => {
+                self.ppu.render_scanline(self.mode);
+            },
+            LcdResults::BlankFrame => {
+                // LCD is off, but the frontend still needs to present a (blank) frame
+                self.frame_ready = true;
+                self.keypad_pal_frames = self.keypad_pal_frames.saturating_sub(1);
+                if self.mode == GB::SGB {
+                    self.sgb.update_frame(&self.ppu.render_shades());
+                }
+            },
+            _ => {
+                // Do nothing
+            }
+        };
+    }
+
+    /// ```
+    /// Update OAM DMA
+    ///
+    /// Advances any OAM DMA in progress, which copies one byte per machine cycle
+    ///
+    /// Input:
+    ///     Number of CPU machine cycles which have elapsed (u8)
+    /// ```
+    fn update_oam_dma(&mut self, cycles: u8) {
+        for _ in 0..cycles {
+            let mut dma_data = match self.oam_dma_remaining {
+                Some(dma_data) => dma_data,
+                None => { return; }
+            };
+
+            if dma_data.delay > 0 {
+                dma_data.delay -= 1;
+            } else {
+                let byte = self.read_ram(dma_data.src_addr + dma_data.transferred, None);
+                self.ppu.dma_write_oam(OAM + dma_data.transferred, byte, self.mode);
+                dma_data.transferred += 1;
+            }
+
+            self.oam_dma_remaining = if dma_data.transferred == OAM_DMA_LEN {
+                None
+            } else {
+                Some(dma_data)
+            };
+        }
+    }
+
+    /// ```
+    /// Is OAM DMA blocked
+    ///
+    /// While an OAM DMA is copying data, the CPU can only access HRAM
+    ///
+    /// Input:
+    ///     Address the CPU is accessing (u16)
+    ///
+    /// Output:
+    ///     Whether the CPU access is blocked (bool)
+    /// ```
+    fn is_oam_dma_blocked(&self, addr: u16) -> bool {
+        match self.oam_dma_remaining {
+            Some(dma_data) => {
+                dma_data.delay == 0 && !(HRAM_START..=HRAM_END).contains(&addr)
+            },
+            None => { false }
+        }
+    }
+
+    /// ```
+    /// Set compatibility palette
+    ///
+    /// Colorizes DMG games running on CGB hardware, as the CGB boot ROM does once finished
+    /// Holding a direction and optionally A or B picks one of the keypad palettes instead
+    /// ```
+    fn set_compat_pal(&mut self) {
+        if !self.set_keypad_pal() && self.is_compat_mode() {
+            self.ppu.set_compat_pal(self.rom.get_title_checksum(), self.rom.get_title_letter(3));
+        }
+    }
+
+    /// ```
+    /// Set keypad palette
+    ///
+    /// Switches to the keypad palette for the held buttons, if they form a combination
+    ///
+    /// Output:
+    ///     Whether a keypad palette was chosen (bool)
+    /// ```
+    fn set_keypad_pal(&mut self) -> bool {
+        if !self.is_compat_mode() {
+            return false;
+        }
+
+        let dir = [Buttons::Up, Buttons::Left, Buttons::Down, Buttons::Right].iter().copied().find(|btn| self.io.is_pressed(*btn));
+        let btn = [Buttons::A, Buttons::B].iter().copied().find(|btn| self.io.is_pressed(*btn));
+        match dir.and_then(|dir| Palettes::from_keypad(dir, btn)) {
+            Some(pal) => {
+                self.ppu.set_sys_pal(pal);
+                true
+            },
+            None => { false }
+        }
+    }
+
+    /// ```
+    /// Is compatibility mode
+    ///
+    /// Whether a DMG game is running on CGB hardware
+    ///
+    /// Output:
+    ///     Whether the game is being colorized (bool)
+    /// ```
+    fn is_compat_mode(&self) -> bool {
+        self.model.is_cgb() && self.game_mode == GB::DMG
+    }
+
+    /// ```
+    /// Switch speed
+    ///
+    /// Performs a CGB speed switch, if one has been armed via KEY1
+    ///
+    /// Output:
+    ///     Whether a speed switch occurred (bool)
+    /// ```
+    fn switch_speed(&mut self) -> bool {
+        if self.mode != GB::CGB || !self.speed_switch_armed {
+            return false;
+        }
+
+        self.double_speed = !self.double_speed;
+        self.speed_switch_armed = false;
+        self.speed_switch_delay = SPEED_SWITCH_CYCLES;
+        true
+    }
+
     /// ```
     /// OAM DMA transfer
     ///
-    /// Copies array of memory from specified area to OAM memory
+    /// Begins copying array of memory from specified area to OAM memory
+    /// The transfer itself takes 160 machine cycles, see update_oam_dma
     ///
     /// Input:
     ///     Upper byte of source memory location (u8)
-    ///     System mode (GB)
     /// ```
-    fn oam_dma(&mut self, val: u8, mode: GB) {
+    fn oam_dma(&mut self, val: u8) {
         // If value is $XX, then copy $XX00-$XX9F into OAM RAM
+        // Writing while a transfer is in progress restarts it
+        self.dma_reg = val;
         let source_addr = (val as u16).wrapping_shl(BYTE as u32);
-        let dest_addr = OAM;
-
-        for i in 0..0xA0 {
-            let byte = self.read_ram(source_addr + i, None, mode);
-            self.write_ram(dest_addr + i, byte, mode);
-        }
+        self.oam_dma_remaining = Some(
+            OAM_DMA {
+                src_addr: source_addr,
+                transferred: 0,
+                delay: OAM_DMA_STARTUP,
+            }
+        );
     }
 
     /// ```
     /// VRAM DMA transfer
     ///
-    /// Copies sprite data from stored area into VRAM, initiated when data written to HDMA5
+    /// Starts or stops a VRAM DMA transfer, initiated when data written to HDMA5
     ///
     /// Input:
-    ///     Length in bytes to transfer, if any (Option<u8>)
+    ///     Value written to HDMA5 (u8)
     /// ```
-    fn vram_dma(&mut self, val: Option<u8>) {
+    fn vram_dma(&mut self, val: u8) {
         // Game Boy Color only, source and destination areas are encoded as such:
         // $FF51 - DMA Source, High
         // $FF52 - DMA Source, Low
         // $FF53 - DMA Destination, High
         // $FF54 - DMA Destination, Low
         // $FF55 - DMA Length
-        match &self.vram_dma_remaining {
-            Some(mut dma_data) => {
-                if dma_data.active {
-                    if let Some(pause_data) = val {
-                        // If newly written data to HDMA5 has 7th bit clear, halt transfer
-                        // This effectively ends the transfer, but metadata needs to be available to be read
-                        if !pause_data.get_bit(7) {
-                            dma_data.active = false;
-                            return;
-                        }
-                    }
+        if let Some(mut dma_data) = self.vram_dma_remaining {
+            // If newly written data to HDMA5 has 7th bit clear, halt transfer
+            // This effectively ends the transfer, but metadata needs to be available to be read
+            if dma_data.active && !val.get_bit(7) {
+                dma_data.active = false;
+                self.vram_dma_remaining = Some(dma_data);
+                return;
+            }
+        }
 
-                    // Complete data transfer of at most $16 bytes, noting if we're done
-                    let scanline = self.read_ram(LY, None, GB::CGB);
-                    if scanline != dma_data.last_scanline {
-                        let remaining = min(VRAM_DMA_PER_HBLANK, dma_data.len - dma_data.transferred);
-                        for i in 0..remaining {
-                            let byte = self.read_ram(dma_data.src_addr + dma_data.transferred + i, None, GB::CGB);
-                            self.write_ram(dma_data.dst_addr + dma_data.transferred + i, byte, GB::CGB);
-                        }
-                        dma_data.transferred += remaining;
-                        if dma_data.transferred == dma_data.len {
-                            self.vram_dma_remaining = None;
-                        } else {
-                            dma_data.last_scanline = scanline;
-                        }
-                    }
-                } else if let Some(raw_transfer_len) = val {
-                    // If the previous transfer is no longer active, but new data has come in, start a new transfer
-                    self.vram_dma_helper(raw_transfer_len);
-                }
-            },
-            None => {
-                // Only initiate a new VRAM DMA transfer if a value was written to HDMA5
-                if let Some(raw_transfer_len) = val {
-                    self.vram_dma_helper(raw_transfer_len);
-                }
+        self.vram_dma_helper(val);
+    }
+
+    /// ```
+    /// HBLANK DMA
+    ///
+    /// Copies the next block of an active HBLANK VRAM DMA transfer
+    /// ```
+    fn hblank_dma(&mut self) {
+        if let Some(mut dma_data) = self.vram_dma_remaining {
+            if !dma_data.active {
+                return;
             }
+
+            let remaining = min(VRAM_DMA_PER_HBLANK, dma_data.len - dma_data.transferred);
+            self.vram_dma_copy(dma_data.src_addr + dma_data.transferred, dma_data.dst_addr + dma_data.transferred, remaining);
+            dma_data.transferred += remaining;
+            self.vram_dma_remaining = if dma_data.transferred == dma_data.len {
+                None
+            } else {
+                Some(dma_data)
+            };
         }
     }
 
     /// ```
     /// VRAM DMA helper
     ///
-    /// Performs a complete VRAM DMA transfer of the entire specified data amount
+    /// Begins a VRAM DMA transfer of the entire specified data amount
+    /// General purpose transfers are completed immediately, while HBLANK transfers
+    /// copy their first block now if already in HBLANK
     ///
     /// Input:
-    ///     Amount of bytes to transfer (u8)
+    ///     Value written to HDMA5 (u8)
     /// ```
     fn vram_dma_helper(&mut self, raw_transfer_len: u8) {
-        let src_addr_high = self.read_ram(HDMA1_REG, None, GB::CGB);
-        let src_addr_low = self.read_ram(HDMA2_REG, None, GB::CGB);
-        let dst_addr_high = self.read_ram(HDMA3_REG, None, GB::CGB);
-        let dst_addr_low = self.read_ram(HDMA4_REG, None, GB::CGB);
+        let src_addr_high = self.read_ram(HDMA1_REG, None);
+        let src_addr_low = self.read_ram(HDMA2_REG, None);
+        let dst_addr_high = self.read_ram(HDMA3_REG, None);
+        let dst_addr_low = self.read_ram(HDMA4_REG, None);
 
         let src_addr = merge_bytes(src_addr_high, src_addr_low) & 0xFFF0; // Lower 4 bits are always zero
-        let dst_addr = merge_bytes(dst_addr_high, dst_addr_low) & 0xFFF0; // Lower 4 bits are ignored
+        let dst_addr = merge_bytes(dst_addr_high, dst_addr_low) & 0x1FF0; // Lower 4 bits are ignored, always within VRAM
 
         // Transfer length is (lower 7 bits of HDMA5 value) * $10 + 1
         let transfer_len = (((raw_transfer_len as u16) & 0b0111_1111) + 1) * 0x10;
@@ -465,27 +1197,486 @@ impl Bus {
 
         if hblank_transfer {
             // If 7th bit was set, then we transfer $10 bits at a time during each HBLANK scanline
-            for i in 0..VRAM_DMA_PER_HBLANK {
-                let byte = self.read_ram(src_addr + i, None, GB::CGB);
-                self.write_ram(dst_addr + i, byte, GB::CGB);
-            }
-
             self.vram_dma_remaining = Some(
                 VRAM_DMA {
                     src_addr: src_addr,
                     dst_addr: dst_addr,
                     len: transfer_len,
-                    transferred: VRAM_DMA_PER_HBLANK,
-                    last_scanline: self.read_ram(LY, None, GB::CGB),
+                    transferred: 0,
                     active: true,
                 }
             );
+
+            if self.ppu.get_lcd_mode() == LcdModeType::HBLANK {
+                self.hblank_dma();
+            }
         } else {
             // Otherwise, simply transfer all data at once
-            for i in 0..transfer_len {
-                let byte = self.read_ram(src_addr + i, None, GB::CGB);
-                self.write_ram(dst_addr + i, byte, GB::CGB);
+            self.vram_dma_remaining = None;
+            self.vram_dma_copy(src_addr, dst_addr, transfer_len);
+        }
+    }
+
+    /// ```
+    /// VRAM DMA copy
+    ///
+    /// Copies data into VRAM, and charges the CPU the stall cycles for doing so
+    /// Sources within VRAM or $E000 and above are not valid, and read as $FF
+    ///
+    /// Inputs:
+    ///     Source address (u16)
+    ///     Destination offset into VRAM (u16)
+    ///     Number of bytes to copy (u16)
+    /// ```
+    fn vram_dma_copy(&mut self, src_addr: u16, dst_offset: u16, len: u16) {
+        for i in 0..len {
+            let src = src_addr.wrapping_add(i);
+            let byte = match src {
+                VRAM_START..=VRAM_END | ECHO_START..=0xFFFF => { 0xFF },
+                _ => { self.read_ram(src, None) }
+            };
+            let dst = VRAM_START | (dst_offset.wrapping_add(i) & 0x1FFF);
+            self.write_ram(dst, byte);
+        }
+        self.vram_dma_stall += (len / VRAM_DMA_PER_HBLANK) * VRAM_DMA_BLOCK_CYCLES;
+    }
+}
+
+impl MemoryBus for Bus {
+    fn read(&mut self, addr: u16) -> u8 {
+        // Reads outside of HRAM return $FF while an OAM DMA is running
+        if self.is_oam_dma_blocked(addr) {
+            return 0xFF;
+        }
+        self.read_ram(addr, None)
+    }
+
+    fn write(&mut self, addr: u16, val: u8) {
+        // Writes outside of HRAM are ignored while an OAM DMA is running
+        if !self.is_oam_dma_blocked(addr) {
+            self.write_ram(addr, val);
+        }
+    }
+
+    fn peek(&self, addr: u16) -> u8 {
+        self.read_ram(addr, None)
+    }
+
+    fn poke(&mut self, addr: u16, val: u8) {
+        self.write_ram(addr, val);
+    }
+
+    fn tick(&mut self, cycles: u8) {
+        self.advance(cycles);
+    }
+
+    fn set_halted(&mut self, halted: bool) {
+        self.cpu_halted = halted;
+    }
+
+    fn stop(&mut self) -> bool {
+        self.timer.write_timer(DIV, 0);
+
+        // On CGB, STOP performs an armed speed switch instead of entering low power mode
+        if self.switch_speed() {
+            return false;
+        }
+
+        // If a button is already being held, the system does not enter STOP mode
+        if self.is_joypad_line_low() {
+            return false;
+        }
+
+        self.stopped_dots = 0;
+        true
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::cpu::Cpu;
+    use crate::ppu::LY;
+    use crate::ppu::palette::{gbc2rgba, Palette};
+    use crate::test_rom;
+
+    const LCDC_REG: u16 = 0xFF40;
+    const BGP_REG: u16 = 0xFF47;
+    const STAT_REG: u16 = 0xFF41;
+    const NR52_REG: u16 = 0xFF26;
+    const RP_REG: u16 = 0xFF56;
+
+    // Runs a frame and returns the color of the top left pixel
+    fn frame_color(gb: &mut Cpu) -> [u8; COLOR_CHANNELS] {
+        while !gb.tick() {}
+        let screen = gb.render();
+        [screen[0], screen[1], screen[2], screen[3]]
+    }
+
+    // Runs the bus one machine cycle at a time until a frame is ready, returning how many it took
+    fn frame_m_cycles(bus: &mut Bus) -> usize {
+        let mut m_cycles = 0;
+        loop {
+            bus.tick(1);
+            m_cycles += 1;
+            if bus.take_frame_ready() {
+                return m_cycles;
             }
         }
     }
+
+    // Bus running in CGB mode with the LCD on
+    fn cgb_bus() -> Bus {
+        let mut bus = Bus::new();
+        bus.mode = GB::CGB;
+        bus.write_ram(LCDC_REG, 0x91);
+        bus
+    }
+
+    fn boot_colorized() -> Cpu {
+        let mut rom = test_rom::build(&[0x18, 0xFE]);
+        test_rom::set_nintendo_title(&mut rom, b"TETRIS");
+        let mut gb = test_rom::boot(&rom, Some(Model::CGB));
+        // Every BG pixel uses the darkest shade
+        gb.write_ram(BGP_REG, 0xFF);
+        // The first frame after the LCD is enabled is blank
+        frame_color(&mut gb);
+        gb
+    }
+
+    fn keypad_color(pal: Palettes) -> [u8; COLOR_CHANNELS] {
+        let mut palette = Palette::new();
+        palette.set_sys_pal(pal);
+        palette.get_bg_pal()[3]
+    }
+
+    #[test]
+    fn frame_takes_70224_dots() {
+        let mut bus = Bus::new();
+        bus.write_ram(LCDC_REG, 0x91);
+        frame_m_cycles(&mut bus);
+        assert_eq!(4 * frame_m_cycles(&mut bus), FRAME_LEN);
+    }
+
+    #[test]
+    fn speed_switch_on_stop() {
+        let mut bus = cgb_bus();
+        bus.write_ram(KEY1_REG, 0x01);
+        assert_eq!(bus.read_ram(KEY1_REG, None), 0x7F);
+        for _ in 0..0x100 {
+            bus.tick(1);
+        }
+        assert_ne!(bus.read_ram(DIV, None), 0x00);
+
+        // STOP performs the switch instead of stopping
+        assert!(!MemoryBus::stop(&mut bus));
+        assert_eq!(bus.read_ram(KEY1_REG, None), 0xFE);
+        assert_eq!(bus.read_ram(DIV, None), 0x00);
+
+        let mut stalled = 0;
+        while bus.stall_cycle() {
+            stalled += 1;
+        }
+        assert_eq!(stalled, SPEED_SWITCH_CYCLES);
+    }
+
+    #[test]
+    fn double_speed_runs_lcd_at_half_rate() {
+        let mut bus = cgb_bus();
+        bus.write_ram(KEY1_REG, 0x01);
+        MemoryBus::stop(&mut bus);
+        while bus.stall_cycle() {}
+
+        frame_m_cycles(&mut bus);
+        assert_eq!(2 * frame_m_cycles(&mut bus), FRAME_LEN);
+
+        // Switching back returns to normal speed
+        bus.write_ram(KEY1_REG, 0x01);
+        MemoryBus::stop(&mut bus);
+        while bus.stall_cycle() {}
+        assert_eq!(bus.read_ram(KEY1_REG, None), 0x7E);
+        frame_m_cycles(&mut bus);
+        assert_eq!(4 * frame_m_cycles(&mut bus), FRAME_LEN);
+    }
+
+    #[test]
+    fn no_speed_switch_on_dmg() {
+        let mut bus = Bus::new();
+        bus.write_ram(KEY1_REG, 0x01);
+        assert!(MemoryBus::stop(&mut bus));
+        assert!(!bus.double_speed);
+        assert!(!bus.stall_cycle());
+    }
+
+    #[test]
+    fn oam_dma_blocks_non_hram_for_160_cycles() {
+        let mut bus = Bus::new();
+        for i in 0..OAM_DMA_LEN {
+            bus.write_ram(WRAM_START + i, i as u8);
+        }
+        bus.write_ram(HRAM_START, 0x12);
+
+        MemoryBus::write(&mut bus, DMA_REG, (WRAM_START >> 8) as u8);
+        // After a one cycle startup, only HRAM can be accessed while the data is copied
+        assert_eq!(MemoryBus::read(&mut bus, WRAM_START), 0x00);
+        bus.tick(1);
+        for _ in 0..OAM_DMA_LEN {
+            assert_eq!(MemoryBus::read(&mut bus, WRAM_START), 0xFF);
+            assert_eq!(MemoryBus::read(&mut bus, HRAM_START), 0x12);
+            bus.tick(1);
+        }
+        assert_eq!(MemoryBus::read(&mut bus, WRAM_START), 0x00);
+
+        for i in 0..OAM_DMA_LEN {
+            assert_eq!(bus.read_ram(OAM + i, None), i as u8);
+        }
+    }
+
+    #[test]
+    fn oam_dma_ignores_blocked_writes() {
+        let mut bus = Bus::new();
+        bus.write_ram(WRAM_START + OAM_DMA_LEN, 0x34);
+        MemoryBus::write(&mut bus, DMA_REG, (WRAM_START >> 8) as u8);
+        bus.tick(2);
+        MemoryBus::write(&mut bus, WRAM_START + OAM_DMA_LEN, 0x56);
+        MemoryBus::write(&mut bus, HRAM_START, 0x78);
+        assert_eq!(bus.read_ram(WRAM_START + OAM_DMA_LEN, None), 0x34);
+        assert_eq!(bus.read_ram(HRAM_START, None), 0x78);
+    }
+
+    // Sets up a VRAM DMA from WRAM_START to the start of VRAM, filling the source with the given byte
+    fn start_vram_dma(bus: &mut Bus, byte: u8, hdma5: u8) {
+        for i in 0..0x100 {
+            bus.write_ram(WRAM_START + i, byte);
+        }
+        bus.write_ram(HDMA1_REG, (WRAM_START >> 8) as u8);
+        bus.write_ram(HDMA2_REG, 0x00);
+        bus.write_ram(HDMA3_REG, (VRAM_START >> 8) as u8);
+        bus.write_ram(HDMA4_REG, 0x00);
+        bus.write_ram(HDMA5_REG, hdma5);
+    }
+
+    // Runs the bus until a DMA stall is over, returning how many machine cycles it lasted
+    fn stall_cycles(bus: &mut Bus) -> u16 {
+        bus.tick(1);
+        let mut stalled = 0;
+        while bus.stall_cycle() {
+            stalled += 1;
+        }
+        stalled
+    }
+
+    #[test]
+    fn general_dma_stalls_cpu() {
+        let mut bus = cgb_bus();
+        // Two $10 byte blocks
+        start_vram_dma(&mut bus, 0x5A, 0x01);
+        assert_eq!(bus.read_ram(VRAM_START + 0x1F, None), 0x5A);
+        assert_eq!(bus.read_ram(VRAM_START + 0x20, None), 0x00);
+        assert_eq!(stall_cycles(&mut bus), 2 * VRAM_DMA_BLOCK_CYCLES);
+
+        // Copying takes the same time, so twice as many CPU cycles in double speed
+        bus.double_speed = true;
+        start_vram_dma(&mut bus, 0x5A, 0x01);
+        assert_eq!(stall_cycles(&mut bus), 4 * VRAM_DMA_BLOCK_CYCLES);
+    }
+
+    #[test]
+    fn hblank_dma_paused_while_halted() {
+        let mut bus = cgb_bus();
+        while bus.ppu.get_lcd_mode() != LcdModeType::OAMReadMode {
+            bus.tick(1);
+        }
+        start_vram_dma(&mut bus, 0x5A, 0x80);
+
+        // Let an HBLANK go by while halted
+        MemoryBus::set_halted(&mut bus, true);
+        while bus.ppu.get_lcd_mode() != LcdModeType::HBLANK {
+            bus.tick(1);
+        }
+        while bus.ppu.get_lcd_mode() == LcdModeType::HBLANK {
+            bus.tick(1);
+        }
+        assert_eq!(bus.read_ram(VRAM_START, None), 0x00);
+
+        MemoryBus::set_halted(&mut bus, false);
+        while bus.ppu.get_lcd_mode() != LcdModeType::HBLANK {
+            bus.tick(1);
+        }
+        assert_eq!(bus.read_ram(VRAM_START, None), 0x5A);
+        assert_eq!(bus.read_ram(VRAM_START + VRAM_DMA_PER_HBLANK, None), 0x00);
+        assert!(bus.vram_dma_remaining.is_none());
+    }
+
+    #[test]
+    fn keypad_palette_selectable_at_startup() {
+        let mut gb = boot_colorized();
+        let compat = frame_color(&mut gb);
+        // Tetris' darkest BG color is black
+        assert_eq!(compat, gbc2rgba(0x00, 0x00));
+        assert_ne!(compat, keypad_color(Palettes::INVERTED));
+
+        gb.toggle_button(Buttons::B, true);
+        gb.toggle_button(Buttons::Right, true);
+        assert_eq!(frame_color(&mut gb), keypad_color(Palettes::INVERTED));
+
+        // Releasing the buttons keeps the chosen palette
+        gb.toggle_button(Buttons::B, false);
+        gb.toggle_button(Buttons::Right, false);
+        assert_eq!(frame_color(&mut gb), keypad_color(Palettes::INVERTED));
+    }
+
+    #[test]
+    fn keypad_palette_locked_after_startup() {
+        let mut gb = boot_colorized();
+        for _ in 0..KEYPAD_PAL_FRAMES {
+            frame_color(&mut gb);
+        }
+        let compat = frame_color(&mut gb);
+
+        gb.toggle_button(Buttons::Left, true);
+        assert_eq!(frame_color(&mut gb), compat);
+    }
+
+    #[test]
+    fn keypad_palette_ignored_on_dmg() {
+        let mut gb = test_rom::boot(&test_rom::build(&[0x18, 0xFE]), Some(Model::DMG));
+        gb.write_ram(BGP_REG, 0xFF);
+        frame_color(&mut gb);
+        let color = frame_color(&mut gb);
+        gb.toggle_button(Buttons::Left, true);
+        assert_eq!(frame_color(&mut gb), color);
+        assert_eq!(color, keypad_color(Palettes::GRAYSCALE));
+    }
+
+    // Sends MASK_EN with the screen blacked out, through the joypad register
+    fn send_mask_black(gb: &mut Cpu) {
+        let mut packet = [0u8; 16];
+        packet[0] = (0x17 << 3) | 1; // MASK_EN, one packet
+        packet[1] = 2; // Black screen
+        gb.write_ram(JOYPAD_REG, 0x00);
+        gb.write_ram(JOYPAD_REG, 0x30);
+        for bit in 0..(8 * packet.len()) {
+            gb.write_ram(JOYPAD_REG, if packet[bit / 8].get_bit((bit % 8) as u8) { 0x10 } else { 0x20 });
+            gb.write_ram(JOYPAD_REG, 0x30);
+        }
+        gb.write_ram(JOYPAD_REG, 0x20);
+        gb.write_ram(JOYPAD_REG, 0x30);
+    }
+
+    #[test]
+    fn sgb_packets_from_joypad() {
+        let mut gb = test_rom::boot(&test_rom::build_sgb(&[0x18, 0xFE]), Some(Model::SGB));
+        frame_color(&mut gb);
+        assert_ne!(frame_color(&mut gb), [0, 0, 0, 0xFF]);
+
+        send_mask_black(&mut gb);
+        assert_eq!(frame_color(&mut gb), [0, 0, 0, 0xFF]);
+    }
+
+    #[test]
+    fn sgb_ignores_games_without_support() {
+        let mut gb = test_rom::boot(&test_rom::build(&[0x18, 0xFE]), Some(Model::SGB));
+        frame_color(&mut gb);
+        let color = frame_color(&mut gb);
+
+        send_mask_black(&mut gb);
+        assert_eq!(frame_color(&mut gb), color);
+        assert_ne!(color, [0, 0, 0, 0xFF]);
+    }
+
+    // Reads an I/O register straight after the boot ROM would have handed over
+    fn post_boot_read(rom: &[u8], model: Model, addr: u16) -> u8 {
+        test_rom::boot(rom, Some(model)).get_bus().read_ram(addr, None)
+    }
+
+    #[test]
+    fn post_boot_dmg0_in_vblank() {
+        let rom = test_rom::build(&[0x18, 0xFE]);
+        assert_eq!(post_boot_read(&rom, Model::DMG0, LY), 0x91);
+        assert_eq!(post_boot_read(&rom, Model::DMG0, STAT_REG), 0x81);
+        assert_eq!(post_boot_read(&rom, Model::DMG, LY), 0x00);
+    }
+
+    #[test]
+    fn post_boot_cgb_registers_open_on_dmg() {
+        let rom = test_rom::build(&[0x18, 0xFE]);
+        for addr in [KEY1_REG, VBK, SVBK_REG, HDMA1_REG, HDMA5_REG] {
+            assert_eq!(post_boot_read(&rom, Model::DMG, addr), 0xFF);
+        }
+        assert_eq!(post_boot_read(&rom, Model::DMG, SC), 0x7E);
+        assert_eq!(post_boot_read(&rom, Model::SGB, NR52_REG), 0xF0);
+    }
+
+    #[test]
+    fn post_boot_cgb() {
+        let rom = test_rom::build_cgb(&[0x18, 0xFE]);
+        assert_eq!(post_boot_read(&rom, Model::CGB, KEY1_REG), 0x7E);
+        assert_eq!(post_boot_read(&rom, Model::CGB, VBK), 0xFE);
+        assert_eq!(post_boot_read(&rom, Model::CGB, SC), 0x7F);
+    }
+
+    #[test]
+    fn post_boot_agb_matches_cgb() {
+        let rom = test_rom::build_cgb(&[0x18, 0xFE]);
+        for addr in [SC, NR52_REG, KEY1_REG, VBK, RP_REG] {
+            assert_eq!(post_boot_read(&rom, Model::AGB, addr), post_boot_read(&rom, Model::CGB, addr));
+        }
+        assert_eq!(post_boot_read(&rom, Model::AGB, RP_REG), 0x3E);
+    }
+
+    const BOOT_BYTE: u8 = 0xBB;
+
+    // Bus with a boot ROM of the given size mapped over the cartridge, every byte of it set to BOOT_BYTE
+    fn boot_rom_bus(size: usize, rom: &[u8]) -> Bus {
+        let mut bus = Bus::new();
+        assert!(bus.load_boot_rom(&vec![BOOT_BYTE; size]));
+        bus.load_game(rom, false);
+        assert!(bus.is_boot_rom_mapped());
+        bus
+    }
+
+    #[test]
+    fn dmg_boot_rom_overlays_first_page() {
+        let rom = test_rom::build(&[0x18, 0xFE]);
+        let bus = boot_rom_bus(DMG_BOOT_ROM_SIZE, &rom);
+        for addr in 0x0000..0x0100 {
+            assert_eq!(bus.read_ram(addr, None), BOOT_BYTE, "${:04X}", addr);
+        }
+        for addr in 0x0100..0x0200 {
+            assert_eq!(bus.read_ram(addr, None), rom[addr as usize], "${:04X}", addr);
+        }
+    }
+
+    #[test]
+    fn cgb_boot_rom_leaves_header_visible() {
+        let rom = test_rom::build_cgb(&[0x18, 0xFE]);
+        let bus = boot_rom_bus(CGB_BOOT_ROM_SIZE, &rom);
+        for addr in (0x0000..0x0100).chain(0x0200..0x0900) {
+            assert_eq!(bus.read_ram(addr, None), BOOT_BYTE, "${:04X}", addr);
+        }
+        for addr in (0x0100..0x0200).chain(0x0900..0x0A00) {
+            assert_eq!(bus.read_ram(addr, None), rom[addr as usize], "${:04X}", addr);
+        }
+    }
+
+    #[test]
+    fn boot_rom_unmapped_for_good() {
+        let rom = test_rom::build(&[0x18, 0xFE]);
+        let mut bus = boot_rom_bus(DMG_BOOT_ROM_SIZE, &rom);
+        // Only bit 0 unmaps the boot ROM
+        bus.write_ram(BOOT_REG, 0xFE);
+        assert!(bus.is_boot_rom_mapped());
+        assert_eq!(bus.read_ram(0x0000, None), BOOT_BYTE);
+
+        bus.write_ram(BOOT_REG, 0x01);
+        assert!(!bus.is_boot_rom_mapped());
+        assert_eq!(bus.read_ram(0x0000, None), rom[0x0000]);
+
+        for val in [0x00, 0x01, 0xFE, 0xFF] {
+            bus.write_ram(BOOT_REG, val);
+            assert!(!bus.is_boot_rom_mapped());
+            assert_eq!(bus.read_ram(0x0000, None), rom[0x0000]);
+        }
+    }
 }
@@ -40,8 +40,18 @@ pub const EXT_RAM_STOP: u16     = 0xBFFF;
 const TITLE_ADDR: usize = 0x0134;
 const DMG_TITLE_ADDR_END: usize = 0x013F;
 const CGB_FLAG_ADDR: usize = 0x0143;
+const TITLE_ADDR_END: usize = 0x0143; // The boot ROM's title checksum includes the CGB flag
+const NEW_LICENSEE_ADDR: usize = 0x0144;
+const SGB_FLAG_ADDR: usize = 0x0146;
 const MBC_TYPE_ADDR: usize = 0x0147;
 const RAM_SIZE_ADDR: usize = 0x0149;
+const OLD_LICENSEE_ADDR: usize = 0x014B;
+const HEADER_CHECKSUM_ADDR: usize = 0x014D;
+
+const NINTENDO_OLD_LICENSEE: u8 = 0x01;
+const USE_NEW_LICENSEE: u8 = 0x33;
+const NINTENDO_NEW_LICENSEE: &[u8] = b"01";
+const SGB_SUPPORT_FLAG: u8 = 0x03;
 
 const DMG_CGB_FLAG: u8  = 0x80;
 const CGB_ONLY_FLAG: u8 = 0xC0;
@@ -262,6 +272,68 @@ impl Cart {
         from_utf8(data).unwrap()
     }
 
+    /// ```
+    /// Get header checksum
+    ///
+    /// Returns the checksum byte stored in the cartridge header
+    ///
+    /// Output:
+    ///     Header checksum (u8)
+    /// ```
+    pub fn get_header_checksum(&self) -> u8 {
+        self.rom[HEADER_CHECKSUM_ADDR]
+    }
+
+    /// ```
+    /// Get title checksum
+    ///
+    /// The CGB boot ROM sums the title bytes of games published by Nintendo,
+    /// which it uses to pick a color palette for DMG games
+    ///
+    /// Output:
+    ///     Sum of the title bytes, or None if not a Nintendo game (Option<u8>)
+    /// ```
+    pub fn get_title_checksum(&self) -> Option<u8> {
+        let nintendo = match self.rom[OLD_LICENSEE_ADDR] {
+            NINTENDO_OLD_LICENSEE => { true },
+            USE_NEW_LICENSEE => { &self.rom[NEW_LICENSEE_ADDR..(NEW_LICENSEE_ADDR + 2)] == NINTENDO_NEW_LICENSEE },
+            _ => { false }
+        };
+
+        if nintendo {
+            Some(self.rom[TITLE_ADDR..=TITLE_ADDR_END].iter().fold(0, |sum: u8, byte| sum.wrapping_add(*byte)))
+        } else {
+            None
+        }
+    }
+
+    /// ```
+    /// Supports SGB
+    ///
+    /// Whether the game uses Super Game Boy functions. The SGB ignores commands from any other game
+    ///
+    /// Output:
+    ///     Whether the SGB flag is set (bool)
+    /// ```
+    pub fn supports_sgb(&self) -> bool {
+        self.rom[SGB_FLAG_ADDR] == SGB_SUPPORT_FLAG && self.rom[OLD_LICENSEE_ADDR] == USE_NEW_LICENSEE
+    }
+
+    /// ```
+    /// Get title letter
+    ///
+    /// Returns a single byte of the title, which the CGB boot ROM uses to tell apart games with the same title checksum
+    ///
+    /// Input:
+    ///     Index into title (usize)
+    ///
+    /// Output:
+    ///     Title byte (u8)
+    /// ```
+    pub fn get_title_letter(&self, index: usize) -> u8 {
+        self.rom[TITLE_ADDR + index]
+    }
+
     /// ```
     /// Get ROM bank number
     ///
@@ -0,0 +1,290 @@
+// Memory buses which the CPU can be connected to
+
+// =============
+// = Constants =
+// =============
+const FLAT_RAM_SIZE: usize = 0x10000;
+
+/// ```
+/// Memory bus
+///
+/// Everything the CPU is connected to. Each access made by an instruction takes one machine cycle,
+/// with tick called to run the rest of the system for that cycle before the access occurs.
+/// This allows the CPU to run against the full Game Boy (Bus), or any other memory,
+/// such as a flat test RAM, a GBS music player, or an instrumented bus for tracing
+/// ```
+pub trait MemoryBus {
+    /// ```
+    /// Read
+    ///
+    /// Reads a byte as part of a CPU memory access
+    ///
+    /// Input:
+    ///     Address (u16)
+    ///
+    /// Output:
+    ///     Byte at address (u8)
+    /// ```
+    fn read(&mut self, addr: u16) -> u8;
+
+    /// ```
+    /// Write
+    ///
+    /// Writes a byte as part of a CPU memory access
+    ///
+    /// Inputs:
+    ///     Address (u16)
+    ///     Byte to write (u8)
+    /// ```
+    fn write(&mut self, addr: u16, val: u8);
+
+    /// ```
+    /// Peek
+    ///
+    /// Reads a byte outside of any memory access, such as the CPU checking the interrupt registers
+    /// Must not have any side effects
+    ///
+    /// Input:
+    ///     Address (u16)
+    ///
+    /// Output:
+    ///     Byte at address (u8)
+    /// ```
+    fn peek(&self, addr: u16) -> u8;
+
+    /// ```
+    /// Poke
+    ///
+    /// Writes a byte without the restrictions of a regular memory access,
+    /// as the CPU does while dispatching an interrupt
+    ///
+    /// Inputs:
+    ///     Address (u16)
+    ///     Byte to write (u8)
+    /// ```
+    fn poke(&mut self, addr: u16, val: u8) {
+        self.write(addr, val);
+    }
+
+    /// ```
+    /// Tick
+    ///
+    /// Runs the rest of the system for the given number of CPU machine cycles
+    ///
+    /// Input:
+    ///     Number of machine cycles (u8)
+    /// ```
+    fn tick(&mut self, _cycles: u8) {}
+
+    /// ```
+    /// Set halted
+    ///
+    /// Called when the CPU enters or leaves HALT
+    ///
+    /// Input:
+    ///     Whether the CPU is now halted (bool)
+    /// ```
+    fn set_halted(&mut self, _halted: bool) {}
+
+    /// ```
+    /// Stop
+    ///
+    /// Called when the CPU executes STOP
+    ///
+    /// Output:
+    ///     Whether the CPU should enter STOP mode until woken by the frontend (bool)
+    /// ```
+    fn stop(&mut self) -> bool {
+        false
+    }
+}
+
+// Allows the bus to be chosen at runtime, i.e. Cpu<Box<dyn MemoryBus>>
+impl<B: MemoryBus + ?Sized> MemoryBus for Box<B> {
+    fn read(&mut self, addr: u16) -> u8 {
+        (**self).read(addr)
+    }
+
+    fn write(&mut self, addr: u16, val: u8) {
+        (**self).write(addr, val);
+    }
+
+    fn peek(&self, addr: u16) -> u8 {
+        (**self).peek(addr)
+    }
+
+    fn poke(&mut self, addr: u16, val: u8) {
+        (**self).poke(addr, val);
+    }
+
+    fn tick(&mut self, cycles: u8) {
+        (**self).tick(cycles);
+    }
+
+    fn set_halted(&mut self, halted: bool) {
+        (**self).set_halted(halted);
+    }
+
+    fn stop(&mut self) -> bool {
+        (**self).stop()
+    }
+}
+
+/// ```
+/// Flat bus
+///
+/// 64 KiB of plain RAM, with no other hardware attached
+/// ```
+pub struct FlatBus {
+    ram: Vec<u8>,
+}
+
+impl Default for FlatBus {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+impl FlatBus {
+    pub fn new() -> FlatBus {
+        FlatBus {
+            ram: vec![0; FLAT_RAM_SIZE],
+        }
+    }
+
+    /// ```
+    /// Load
+    ///
+    /// Copies data into RAM, wrapping around past $FFFF
+    ///
+    /// Inputs:
+    ///     Starting address (u16)
+    ///     Data to copy (&[u8])
+    /// ```
+    pub fn load(&mut self, addr: u16, data: &[u8]) {
+        for (i, byte) in data.iter().enumerate() {
+            let dst = addr.wrapping_add(i as u16);
+            self.ram[dst as usize] = *byte;
+        }
+    }
+}
+
+impl MemoryBus for FlatBus {
+    fn read(&mut self, addr: u16) -> u8 {
+        self.ram[addr as usize]
+    }
+
+    fn write(&mut self, addr: u16, val: u8) {
+        self.ram[addr as usize] = val;
+    }
+
+    fn peek(&self, addr: u16) -> u8 {
+        self.ram[addr as usize]
+    }
+}
+
+#[derive(Copy, Clone, Debug, PartialEq)]
+pub enum BusEvent {
+    Read(u16, u8),
+    Write(u16, u8),
+    Tick(u8),
+}
+
+/// ```
+/// Instrumented bus
+///
+/// Wraps another bus, logging every memory access and tick the CPU makes
+/// Accesses made outside of an instruction's timing (peek and poke) aren't logged
+/// ```
+pub struct InstrumentedBus<B: MemoryBus> {
+    inner: B,
+    log: Vec<BusEvent>,
+}
+
+impl<B: MemoryBus> InstrumentedBus<B> {
+    pub fn new(inner: B) -> InstrumentedBus<B> {
+        InstrumentedBus {
+            inner,
+            log: Vec::new(),
+        }
+    }
+
+    /// ```
+    /// Get log
+    ///
+    /// Returns everything logged since the last clear, in order
+    ///
+    /// Output:
+    ///     Logged events (&[BusEvent])
+    /// ```
+    pub fn get_log(&self) -> &[BusEvent] {
+        &self.log
+    }
+
+    /// ```
+    /// Clear log
+    ///
+    /// Empties the event log
+    /// ```
+    pub fn clear_log(&mut self) {
+        self.log.clear();
+    }
+
+    /// ```
+    /// Get inner
+    ///
+    /// Returns the wrapped bus
+    ///
+    /// Output:
+    ///     Wrapped bus (&B)
+    /// ```
+    pub fn get_inner(&self) -> &B {
+        &self.inner
+    }
+
+    /// ```
+    /// Get inner mut
+    ///
+    /// Returns the wrapped bus, for modification
+    ///
+    /// Output:
+    ///     Wrapped bus (&mut B)
+    /// ```
+    pub fn get_inner_mut(&mut self) -> &mut B {
+        &mut self.inner
+    }
+}
+
+impl<B: MemoryBus> MemoryBus for InstrumentedBus<B> {
+    fn read(&mut self, addr: u16) -> u8 {
+        let val = self.inner.read(addr);
+        self.log.push(BusEvent::Read(addr, val));
+        val
+    }
+
+    fn write(&mut self, addr: u16, val: u8) {
+        self.inner.write(addr, val);
+        self.log.push(BusEvent::Write(addr, val));
+    }
+
+    fn peek(&self, addr: u16) -> u8 {
+        self.inner.peek(addr)
+    }
+
+    fn poke(&mut self, addr: u16, val: u8) {
+        self.inner.poke(addr, val);
+    }
+
+    fn tick(&mut self, cycles: u8) {
+        self.inner.tick(cycles);
+        self.log.push(BusEvent::Tick(cycles));
+    }
+
+    fn set_halted(&mut self, halted: bool) {
+        self.inner.set_halted(halted);
+    }
+
+    fn stop(&mut self) -> bool {
+        self.inner.stop()
+    }
+}
@@ -1,19 +1,19 @@
+pub mod memory_bus;
 pub mod opcodes;
 pub mod timer;
 
 use crate::bus::Bus;
 use crate::io::Buttons;
-use crate::ppu::mode::LcdResults;
 use crate::ppu::palette::Palettes;
+use crate::serial::SerialLink;
 use crate::utils::*;
-use timer::*;
+use memory_bus::MemoryBus;
 
 // =============
 // = Constants =
 // =============
-const IF_REG: u16   = 0xFF0F;   // Interrupt Flag
-const KEY1_REG: u16 = 0xFF4D;   // CGB Speed Switch
-const IE_REG: u16   = 0xFFFF;   // Interrupt Enable
+pub const IF_REG: u16 = 0xFF0F;   // Interrupt Flag
+pub const IE_REG: u16 = 0xFFFF;   // Interrupt Enable
 
 const INTER_PRIORITIES: [Interrupts; 5] = [
     Interrupts::VBLANK,
@@ -60,7 +60,7 @@ pub enum Interrupts {
     JOYPAD
 }
 
-pub struct Cpu {
+pub struct Cpu<B: MemoryBus = Bus> {
     pc: u16,
     sp: u16,
     a: u8,
@@ -71,13 +71,13 @@ pub struct Cpu {
     f: u8,
     h: u8,
     l: u8,
-    mode: GB,
-    timer: Timer,
     interrupt_enabled: bool,
+    ei_pending: bool,
     halted: bool,
-    bus: Bus,
-    dirty_battery_ram: bool,
-    double_speed: bool,
+    halt_bug: bool,
+    stopped: bool,
+    step_cycles: u8,
+    bus: B,
 }
 
 impl Default for Cpu {
@@ -88,61 +88,7 @@ impl Default for Cpu {
 
 impl Cpu {
     pub fn new() -> Cpu {
-        // Magic values from pandocs
-        let mut new_cpu = Cpu {
-            pc: 0x100,
-            sp: 0xFFFE,
-            a: 0x01,
-            b: 0x00,
-            c: 0x13,
-            d: 0x00,
-            e: 0xD8,
-            f: 0xB0,
-            h: 0x01,
-            l: 0x4D,
-            mode: GB::DMG,
-            timer: Timer::new(),
-            interrupt_enabled: false,
-            halted: false,
-            bus: Bus::new(),
-            dirty_battery_ram: false,
-            double_speed: false,
-        };
-
-        // Magic values for RAM initialization
-        new_cpu.write_ram(0xFF05, 0x00);
-        new_cpu.write_ram(0xFF06, 0x00);
-        new_cpu.write_ram(0xFF07, 0x00);
-        new_cpu.write_ram(0xFF10, 0x80);
-        new_cpu.write_ram(0xFF11, 0xBF);
-        new_cpu.write_ram(0xFF12, 0xF3);
-        new_cpu.write_ram(0xFF14, 0xBF);
-        new_cpu.write_ram(0xFF16, 0x3F);
-        new_cpu.write_ram(0xFF17, 0x00);
-        new_cpu.write_ram(0xFF19, 0xBF);
-        new_cpu.write_ram(0xFF1A, 0x7F);
-        new_cpu.write_ram(0xFF1B, 0xFF);
-        new_cpu.write_ram(0xFF1C, 0x9F);
-        new_cpu.write_ram(0xFF1E, 0xBF);
-        new_cpu.write_ram(0xFF20, 0xFF);
-        new_cpu.write_ram(0xFF21, 0x00);
-        new_cpu.write_ram(0xFF22, 0x00);
-        new_cpu.write_ram(0xFF23, 0xBF);
-        new_cpu.write_ram(0xFF24, 0x77);
-        new_cpu.write_ram(0xFF25, 0xF3);
-        new_cpu.write_ram(0xFF26, 0xF1); // $F0 for SGB
-        new_cpu.write_ram(0xFF40, 0x91);
-        new_cpu.write_ram(0xFF42, 0x00);
-        new_cpu.write_ram(0xFF43, 0x00);
-        new_cpu.write_ram(0xFF45, 0x00);
-        new_cpu.write_ram(0xFF47, 0xFC);
-        new_cpu.write_ram(0xFF48, 0xFF);
-        new_cpu.write_ram(0xFF49, 0xFF);
-        new_cpu.write_ram(0xFF4A, 0x00);
-        new_cpu.write_ram(0xFF4B, 0x00);
-        new_cpu.write_ram(0xFF4F, 0x00);
-
-        new_cpu
+        Cpu::with_bus(Bus::new())
     }
 
     /// ```
@@ -154,44 +100,20 @@ impl Cpu {
     ///     Whether or not to render a frame (bool)
     /// ```
     pub fn tick(&mut self) -> bool {
-        let mut draw_time = false;
-
-        // If halted, simply continue counting without executing opcodes
-        let cycles = if self.halted { 1 } else { opcodes::execute(self) };
-
-        let ppu_result = self.bus.update_ppu(cycles, self.mode);
-        if ppu_result.interrupt {
-            self.enable_interrupt(Interrupts::LCD_STAT);
-        }
-
-        // Tick timer
-        let timer_interrupt = self.timer.tick(cycles);
-        if timer_interrupt {
-            self.enable_interrupt(Interrupts::TIMER);
-        }
-
-        match ppu_result.lcd_result {
-            LcdResults::RenderFrame => {
-                // Render the final scanline before rendering frame
-                self.bus.render_scanline(self.mode);
-                // If time to render frame, then VBLANK interrupt is toggled
-                self.enable_interrupt(Interrupts::VBLANK);
-                draw_time = true;
-            },
-            LcdResults::RenderScanline => {
-                self.bus.render_scanline(self.mode);
-            },
-            _ => {
-                // Do nothing
+        if self.stopped {
+            if self.bus.is_joypad_line_low() {
+                self.stopped = false;
+            } else {
+                return self.bus.stopped_tick();
             }
-        };
+        }
 
-        // Check for interrupts
-        if let Some(inter_type) = self.interrupt_check() {
-            self.trigger_interrupt(inter_type);
+        if self.bus.stall_cycle() {
+            return self.bus.take_frame_ready();
         }
 
-        draw_time
+        self.step();
+        self.bus.take_frame_ready()
     }
 
     /// ```
@@ -200,7 +122,7 @@ impl Cpu {
     /// Resets dirty battery RAM flag
     /// ```
     pub fn clean_battery_flag(&mut self) {
-        self.dirty_battery_ram = false;
+        self.bus.clean_battery_flag();
     }
 
     /// ```
@@ -216,30 +138,27 @@ impl Cpu {
     }
 
     /// ```
-    /// Get title
+    /// Render SGB
     ///
-    /// Gets the title for the currently loaded game
+    /// Renders one frame within the Super Game Boy border
     ///
     /// Output:
-    ///     Game name (&str)
+    ///     Array of SGB_SCREEN_WIDTH x SGB_SCREEN_HEIGHT pixels to draw ([u8])
     /// ```
-    pub fn get_title(&self) -> &str {
-        self.bus.get_title(self.mode == GB::CGB)
+    pub fn render_sgb(&self) -> [u8; SGB_DISP_SIZE] {
+        self.bus.render_sgb()
     }
 
     /// ```
-    /// Fetch
+    /// Get title
     ///
-    /// Fetches the byte specified by the PC, increments PC by one
+    /// Gets the title for the currently loaded game
     ///
     /// Output:
-    ///     Byte at the current PC (u8)
+    ///     Game name (&str)
     /// ```
-    pub fn fetch(&mut self) -> u8 {
-        let pc = self.get_pc();
-        let val = self.read_ram(pc, None);
-        self.pc += 1;
-        val
+    pub fn get_title(&self) -> &str {
+        self.bus.get_title(self.bus.get_mode() == GB::CGB)
     }
 
     /// ```
@@ -275,7 +194,7 @@ impl Cpu {
     ///     Whether battery RAM needs to be saved
     /// ```
     pub fn is_battery_dirty(&self) -> bool {
-        self.dirty_battery_ram
+        self.bus.is_battery_dirty()
     }
 
     /// ```
@@ -294,21 +213,65 @@ impl Cpu {
     /// Load game
     ///
     /// Wrapper for the load game functionality
+    /// DMG games on CGB hardware are colorized as the boot ROM would. A keypad palette can be picked by
+    /// holding its buttons during the first couple of seconds, as when the boot ROM shows its logo
     ///
     /// Inputs:
     ///     Game data (&[u8])
     ///     Whether to force DMG mode (bool)
     /// ```
     pub fn load_game(&mut self, rom: &[u8], dmg: bool) {
-        self.mode = self.bus.load_game(rom);
-        if dmg {
-            self.mode = GB::DMG;
-        }
+        let mode = self.bus.load_game(rom, dmg);
 
-        // System determines if hardware is CGB if accumulator is $11 at start
-        if self.mode == GB::CGB {
-            self.set_reg(Regs::A, 0x11);
+        // The boot ROM starts from power on, and sets up the system itself
+        if self.bus.is_boot_rom_mapped() {
+            self.power_on();
+            return;
         }
+
+        self.bus.post_boot();
+        self.set_post_boot_regs(mode);
+    }
+
+    /// ```
+    /// Set model
+    ///
+    /// Chooses which hardware model to emulate for the next loaded game
+    /// If unset, CGB games run on a CGB and all others on a DMG
+    ///
+    /// Input:
+    ///     Model, or None to choose based on the game (Option<Model>)
+    /// ```
+    pub fn set_model(&mut self, model: Option<Model>) {
+        self.bus.set_model(model);
+    }
+
+    /// ```
+    /// Get model
+    ///
+    /// Returns which hardware model is being emulated
+    ///
+    /// Output:
+    ///     Hardware model (Model)
+    /// ```
+    pub fn get_model(&self) -> Model {
+        self.bus.get_model()
+    }
+
+    /// ```
+    /// Load boot ROM
+    ///
+    /// Runs the given boot ROM before the next loaded game, instead of starting with the state it leaves behind
+    /// DMG and MGB boot ROMs are used for DMG games, while CGB boot ROMs are used for all games
+    ///
+    /// Input:
+    ///     Boot ROM data (&[u8])
+    ///
+    /// Output:
+    ///     Whether the data is a valid boot ROM (bool)
+    /// ```
+    pub fn load_boot_rom(&mut self, data: &[u8]) -> bool {
+        self.bus.load_boot_rom(data)
     }
 
     /// ```
@@ -323,6 +286,85 @@ impl Cpu {
         self.bus.set_sys_pal(pal);
     }
 
+    /// ```
+    /// Set strict VRAM access
+    ///
+    /// Toggles whether OAM, VRAM and CGB palette RAM are inaccessible to the CPU
+    /// while the LCD is using them. Off by default.
+    ///
+    /// Input:
+    ///     Whether to enforce access restrictions (bool)
+    /// ```
+    pub fn set_strict_vram_access(&mut self, strict: bool) {
+        self.bus.set_strict_vram_access(strict);
+    }
+
+    /// ```
+    /// Set serial capture
+    ///
+    /// Toggles collecting text sent over the serial port, as test ROMs do to print their results
+    /// Enabling clears any previously collected text
+    ///
+    /// Input:
+    ///     Whether to collect serial output (bool)
+    /// ```
+    pub fn set_serial_capture(&mut self, enabled: bool) {
+        self.bus.set_serial_capture(enabled);
+    }
+
+    /// ```
+    /// Get serial text
+    ///
+    /// Returns the text sent over the serial port since capturing was enabled
+    ///
+    /// Output:
+    ///     Collected text, empty if not capturing (String)
+    /// ```
+    pub fn get_serial_text(&self) -> String {
+        match self.bus.get_serial_capture() {
+            Some(bytes) => { String::from_utf8_lossy(bytes).into_owned() },
+            None => { String::new() }
+        }
+    }
+
+    /// ```
+    /// Get elapsed dots
+    ///
+    /// Total time the system has run for, counted in dots (4.19 MHz), regardless of CPU speed
+    ///
+    /// Output:
+    ///     Number of dots elapsed since power on (u64)
+    /// ```
+    pub fn get_elapsed_dots(&self) -> u64 {
+        self.bus.get_elapsed_dots()
+    }
+
+    /// ```
+    /// Set serial link
+    ///
+    /// Attaches a device to the other end of the link cable
+    /// By default, no cable is connected
+    ///
+    /// Input:
+    ///     Link cable backend (Box<dyn SerialLink>)
+    /// ```
+    pub fn set_serial_link(&mut self, link: Box<dyn SerialLink>) {
+        self.bus.set_serial_link(link);
+    }
+
+    /// ```
+    /// Take serial error
+    ///
+    /// Returns the latest error from the device on the other end of the link cable, such as
+    /// a printout which couldn't be saved. The device keeps running, so this is only for reporting
+    ///
+    /// Output:
+    ///     Error since last called (Option<std::io::Error>)
+    /// ```
+    pub fn take_serial_error(&mut self) -> Option<std::io::Error> {
+        self.bus.take_serial_error()
+    }
+
     /// ```
     /// Press button
     ///
@@ -334,7 +376,7 @@ impl Cpu {
     /// ```
     pub fn toggle_button(&mut self, btn: Buttons, pressed: bool) {
         self.bus.toggle_button(btn, pressed);
-        self.enable_interrupt(Interrupts::JOYPAD);
+        self.bus.request_interrupt(Interrupts::JOYPAD);
     }
 
     /// ```
@@ -349,6 +391,229 @@ impl Cpu {
         self.bus.write_ext_ram(data);
     }
 
+    /// ```
+    /// Read RAM
+    ///
+    /// Returns the byte at the specified address in RAM
+    ///
+    /// Input:
+    ///     Address in RAM (u16)
+    ///     Bank override (Option<u16>)
+    ///
+    /// Output:
+    ///     Byte at specified address (u8)
+    /// ```
+    pub fn read_ram(&self, addr: u16, bank_override: Option<u16>) -> u8 {
+        self.bus.read_ram(addr, bank_override)
+    }
+
+    /// ```
+    /// Write RAM
+    ///
+    /// Writes the specified byte at the specified address
+    ///
+    /// Inputs:
+    ///     Address in RAM (u16)
+    ///     Byte to write (u8)
+    /// ```
+    pub fn write_ram(&mut self, addr: u16, val: u8) {
+        self.bus.write_ram(addr, val);
+    }
+
+}
+
+// Private functions
+impl Cpu {
+    /// ```
+    /// Power on
+    ///
+    /// Clears the registers to their state at power on, with the LCD off, for the boot ROM to start from
+    /// ```
+    fn power_on(&mut self) {
+        for reg in [Regs::A, Regs::B, Regs::C, Regs::D, Regs::E, Regs::F, Regs::H, Regs::L] {
+            self.set_reg(reg, 0x00);
+        }
+        self.set_pc(0x0000);
+        self.set_sp(0x0000);
+        self.bus.write_ram(0xFF40, 0x00);
+    }
+
+    /// ```
+    /// Set post boot registers
+    ///
+    /// Sets the registers as the boot ROM of the current model leaves them, which games check to identify the hardware
+    /// Values from pandocs
+    ///
+    /// Input:
+    ///     Mode the game is running in (GB)
+    /// ```
+    fn set_post_boot_regs(&mut self, mode: GB) {
+        let regs = match self.bus.get_model() {
+            Model::DMG0 => { [0x01, 0x00, 0xFF, 0x13, 0x00, 0xC1, 0x84, 0x03] },
+            Model::DMG | Model::MGB => {
+                // The MGB is detected by its accumulator value, while the flags depend on the header checksum
+                let a = if self.bus.get_model() == Model::MGB { 0xFF } else { 0x01 };
+                let f = if self.bus.get_header_checksum() == 0x00 { 0x80 } else { 0xB0 };
+                [a, f, 0x00, 0x13, 0x00, 0xD8, 0x01, 0x4D]
+            },
+            Model::SGB =>  { [0x01, 0x00, 0x00, 0x14, 0x00, 0x00, 0xC0, 0x60] },
+            Model::SGB2 => { [0xFF, 0x00, 0x00, 0x14, 0x00, 0x00, 0xC0, 0x60] },
+            Model::CGB | Model::AGB => {
+                if mode == GB::CGB {
+                    [0x11, 0x80, 0x00, 0x00, 0xFF, 0x56, 0x00, 0x0D]
+                } else {
+                    // In compatibility mode, the boot ROM leaves behind the title checksum it used to pick a palette
+                    let b = self.bus.get_title_checksum().unwrap_or(0x00);
+                    let hl: u16 = if b == 0x43 || b == 0x58 { 0x991A } else { 0x007C };
+                    [0x11, 0x80, b, 0x00, 0x00, 0x08, hl.get_high_byte(), hl.get_low_byte()]
+                }
+            }
+        };
+
+        for (reg, val) in [Regs::A, Regs::F, Regs::B, Regs::C, Regs::D, Regs::E, Regs::H, Regs::L].iter().zip(regs.iter()) {
+            self.set_reg(*reg, *val);
+        }
+
+        // The AGB boot ROM finishes with an extra INC B, which is how GBA enhanced games detect it
+        if self.bus.get_model() == Model::AGB {
+            self.inc_8(Regs::B);
+        }
+
+        self.set_pc(0x0100);
+        self.set_sp(0xFFFE);
+    }
+}
+
+impl<B: MemoryBus> Cpu<B> {
+    /// ```
+    /// With bus
+    ///
+    /// Creates a CPU connected to the given memory bus, with registers set as after the boot ROM
+    ///
+    /// Input:
+    ///     Memory bus (B)
+    /// ```
+    pub fn with_bus(bus: B) -> Cpu<B> {
+        // Magic values from pandocs
+        Cpu {
+            pc: 0x100,
+            sp: 0xFFFE,
+            a: 0x01,
+            b: 0x00,
+            c: 0x13,
+            d: 0x00,
+            e: 0xD8,
+            f: 0xB0,
+            h: 0x01,
+            l: 0x4D,
+            interrupt_enabled: false,
+            ei_pending: false,
+            halted: false,
+            halt_bug: false,
+            stopped: false,
+            step_cycles: 0,
+            bus,
+        }
+    }
+
+    /// ```
+    /// Step
+    ///
+    /// Executes one instruction, or one machine cycle if halted, then services any pending interrupt
+    /// ```
+    pub fn step(&mut self) {
+        // EI takes effect only after the instruction following it has executed
+        let enable_ime = self.ei_pending;
+
+        // If halted, simply continue counting without executing opcodes
+        // Memory accesses advance the system as they occur, so only the remaining internal cycles are run afterwards
+        self.step_cycles = 0;
+        let cycles = if self.halted { 1 } else { opcodes::execute(self) };
+        let remaining = cycles.saturating_sub(self.step_cycles);
+
+        // Don't enable if the pending EI was cancelled (i.e. by a DI)
+        if enable_ime && self.ei_pending {
+            self.ei_pending = false;
+            self.interrupt_enabled = true;
+        }
+
+        self.bus.tick(remaining);
+
+        // Check for interrupts
+        if self.interrupt_check().is_some() {
+            self.trigger_interrupt();
+        }
+    }
+
+    /// ```
+    /// Get bus
+    ///
+    /// Returns the memory bus the CPU is connected to
+    ///
+    /// Output:
+    ///     Memory bus (&B)
+    /// ```
+    pub fn get_bus(&self) -> &B {
+        &self.bus
+    }
+
+    /// ```
+    /// Get bus mut
+    ///
+    /// Returns the memory bus the CPU is connected to, for modification
+    ///
+    /// Output:
+    ///     Memory bus (&mut B)
+    /// ```
+    pub fn get_bus_mut(&mut self) -> &mut B {
+        &mut self.bus
+    }
+
+    /// ```
+    /// Get IME
+    ///
+    /// Returns whether the interrupt master enable flag is set
+    ///
+    /// Output:
+    ///     IME flag (bool)
+    /// ```
+    pub fn get_ime(&self) -> bool {
+        self.interrupt_enabled
+    }
+
+    /// ```
+    /// Set IME
+    ///
+    /// Sets or clears the interrupt master enable flag
+    ///
+    /// Input:
+    ///     IME flag (bool)
+    /// ```
+    pub fn set_ime(&mut self, enabled: bool) {
+        self.interrupt_enabled = enabled;
+        self.ei_pending = false;
+    }
+
+    /// ```
+    /// Fetch
+    ///
+    /// Fetches the byte specified by the PC, increments PC by one
+    ///
+    /// Output:
+    ///     Byte at the current PC (u8)
+    /// ```
+    pub fn fetch(&mut self) -> u8 {
+        let pc = self.get_pc();
+        let val = self.read_cycle(pc);
+        // The HALT bug causes the PC to fail to increment after the next byte is read
+        if self.halt_bug {
+            self.halt_bug = false;
+        } else {
+            self.pc = self.pc.wrapping_add(1);
+        }
+        val
+    }
+
     /// ```
     /// ADD A d8
     ///
@@ -716,14 +981,12 @@ impl Cpu {
     ///     Value on top of stack (u16)
     /// ```
     pub fn pop(&mut self) -> u16 {
-        // If at $FFFE, then stack is empty, assert?
-        let mut sp = self.get_sp();
-        assert_ne!(sp, 0xFFFE, "Trying to pop when stack is empty");
-        let low = self.read_ram(sp, None);
-        let high = self.read_ram(sp + 1, None);
+        // The stack pointer simply wraps around, even if the stack is empty
+        let sp = self.get_sp();
+        let low = self.read_cycle(sp);
+        let high = self.read_cycle(sp.wrapping_add(1));
         let byte = merge_bytes(high, low);
-        sp += 2;
-        self.set_sp(sp);
+        self.set_sp(sp.wrapping_add(2));
         byte
     }
 
@@ -731,43 +994,62 @@ impl Cpu {
     /// PUSH
     ///
     /// Pushes value onto stack
+    /// Takes an internal cycle to decrement SP before the high byte is written
     ///
     /// Input:
     ///     Value to push onto stack (u16)
     /// ```
     pub fn push(&mut self, val: u16) {
-        let sp = self.get_sp() - 2;
+        let sp = self.get_sp().wrapping_sub(2);
         let high = val.get_high_byte();
         let low = val.get_low_byte();
-        self.write_ram(sp + 1, high);
-        self.write_ram(sp, low);
+        self.internal_cycle();
+        self.write_cycle(sp.wrapping_add(1), high);
+        self.write_cycle(sp, low);
         self.set_sp(sp);
     }
 
     /// ```
-    /// Read RAM
+    /// Read cycle
     ///
-    /// Returns the byte at the specified address in RAM
+    /// Reads a byte from memory as the CPU would, taking one machine cycle
+    /// The rest of the system is advanced before the read occurs
     ///
     /// Input:
     ///     Address in RAM (u16)
-    ///     Bank override (Option<u16>)
     ///
     /// Output:
     ///     Byte at specified address (u8)
     /// ```
-    pub fn read_ram(&self, addr: u16, bank_override: Option<u16>) -> u8 {
-        match addr {
-            DIV..=TAC => { self.timer.read_timer(addr) },
-            KEY1_REG => {
-                if self.mode == GB::CGB {
-                    if self.double_speed { 0x80 } else { 0 }
-                } else {
-                    self.bus.read_ram(addr, bank_override, self.mode)
-                }
-            },
-            _ => { self.bus.read_ram(addr, bank_override, self.mode) }
-        }
+    pub fn read_cycle(&mut self, addr: u16) -> u8 {
+        self.internal_cycle();
+        self.bus.read(addr)
+    }
+
+    /// ```
+    /// Write cycle
+    ///
+    /// Writes a byte to memory as the CPU would, taking one machine cycle
+    /// The rest of the system is advanced before the write occurs
+    ///
+    /// Inputs:
+    ///     Address in RAM (u16)
+    ///     Value to write (u8)
+    /// ```
+    pub fn write_cycle(&mut self, addr: u16, val: u8) {
+        self.internal_cycle();
+        self.bus.write(addr, val);
+    }
+
+    /// ```
+    /// Internal cycle
+    ///
+    /// Advances the rest of the system by one machine cycle during an instruction,
+    /// counting it towards the instruction's total
+    /// ```
+    fn internal_cycle(&mut self) {
+        self.step_cycles += 1;
+        self.bus.tick(1);
     }
 
     /// ```
@@ -1157,9 +1439,9 @@ impl Cpu {
     ///     Whether to set bit to 1 or 0 (bool)
     /// ```
     pub fn write_bit_ram(&mut self, addr: u16, digit: u8, set: bool) {
-        let mut val = self.read_ram(addr, None);
+        let mut val = self.read_cycle(addr);
         val.write_bit(digit, set);
-        self.write_ram(addr, val);
+        self.write_cycle(addr, val);
     }
 
     /// ```
@@ -1179,33 +1461,6 @@ impl Cpu {
         }
     }
 
-    /// ```
-    /// Write RAM
-    ///
-    /// Writes the specified byte at the specified address
-    ///
-    /// Inputs:
-    ///     Address in RAM (u16)
-    ///     Byte to write (u8)
-    /// ```
-    pub fn write_ram(&mut self, addr: u16, val: u8) {
-        match addr {
-            DIV..=TAC => {
-                self.timer.write_timer(addr, val);
-            },
-            KEY1_REG => {
-                if self.mode == GB::CGB {
-                    self.double_speed = val.get_bit(0);
-                } else {
-                    self.dirty_battery_ram |= self.bus.write_ram(addr, val, self.mode);
-                }
-            },
-            _ => {
-                self.dirty_battery_ram |= self.bus.write_ram(addr, val, self.mode);
-            }
-        }
-    }
-
     /// ```
     /// XOR A d8
     ///
@@ -1228,6 +1483,50 @@ impl Cpu {
     // = Private methods =
     // ===================
 
+    /// ```
+    /// Stop
+    ///
+    /// Executes the STOP instruction
+    /// On CGB, this performs an armed speed switch. Otherwise, the system enters a
+    /// low power mode until a joypad input line goes low.
+    /// ```
+    fn stop(&mut self) {
+        // STOP is two bytes long, the second byte is ignored
+        self.fetch();
+        self.stopped = self.bus.stop();
+    }
+
+    /// ```
+    /// Halt
+    ///
+    /// Executes the HALT instruction
+    ///
+    /// If IME is set, the CPU sleeps until an interrupt is serviced.
+    /// If IME is clear, the CPU sleeps until an interrupt is pending, then continues without servicing it.
+    /// However, if IME is clear and an interrupt is already pending, the CPU doesn't halt at all,
+    /// and instead the following byte is read twice (the "HALT bug").
+    /// ```
+    fn halt(&mut self) {
+        if !self.interrupt_enabled && self.is_interrupt_pending() {
+            self.halt_bug = true;
+        } else {
+            self.halted = true;
+            self.bus.set_halted(true);
+        }
+    }
+
+    /// ```
+    /// Is interrupt pending
+    ///
+    /// Whether any enabled interrupt has been requested, regardless of IME
+    ///
+    /// Output:
+    ///     Whether IE & IF is non-zero (bool)
+    /// ```
+    fn is_interrupt_pending(&self) -> bool {
+        self.highest_pending_interrupt().is_some()
+    }
+
     /// ```
     /// Interrupt Check
     ///
@@ -1242,9 +1541,21 @@ impl Cpu {
             return None;
         }
 
+        self.highest_pending_interrupt()
+    }
+
+    /// ```
+    /// Highest pending interrupt
+    ///
+    /// Finds the highest priority interrupt which is both requested and enabled, regardless of IME
+    ///
+    /// Output:
+    ///     Interrupt to be serviced, if any (Option<Interrupts>)
+    /// ```
+    fn highest_pending_interrupt(&self) -> Option<Interrupts> {
         // Interrupt must be requesting to occur
-        let if_reg = self.read_ram(IF_REG, None);
-        let ie_reg = self.read_ram(IE_REG, None);
+        let if_reg = self.bus.peek(IF_REG);
+        let ie_reg = self.bus.peek(IE_REG);
         let valid_interrupt = (if_reg & ie_reg) & 0x1F;
         let mut mask = 0b1;
 
@@ -1284,57 +1595,391 @@ impl Cpu {
     /// ```
     /// Trigger interrupt
     ///
-    /// Triggers an interrupt of the given type
+    /// Wakes the CPU from HALT, and if IME is set, dispatches the highest priority pending interrupt
     ///
-    /// Input:
-    ///     Interrupt type (Interrupts)
+    /// Dispatch takes 5 machine cycles: two idle cycles, two cycles to push PC, and one to jump.
+    /// Which interrupt is serviced is decided after the high byte of PC is pushed.
+    /// If that push overwrote IE ($FFFF) so that no interrupt is pending anymore,
+    /// the dispatch is cancelled and execution continues from $0000.
     /// ```
-    fn trigger_interrupt(&mut self, inter: Interrupts) {
-        let mut if_reg = self.read_ram(IF_REG, None);
-        let vector = self.get_inter_vector(inter);
-        self.halted = false;
+    fn trigger_interrupt(&mut self) {
+        if self.halted {
+            self.halted = false;
+            self.bus.set_halted(false);
+        }
 
         // Save current PC, jump to interrupt vector if master interrupt enabled
         // Otherwise, we simply wake up from halt
-        if self.interrupt_enabled {
-            self.interrupt_enabled = false;
-            self.push(self.get_pc());
-            self.set_pc(vector);
-
-            match inter {
-                Interrupts::VBLANK =>   { if_reg.clear_bit(0) },
-                Interrupts::LCD_STAT => { if_reg.clear_bit(1) },
-                Interrupts::TIMER =>    { if_reg.clear_bit(2) },
-                Interrupts::SERIAL =>   { if_reg.clear_bit(3) },
-                Interrupts::JOYPAD =>   { if_reg.clear_bit(4) },
+        if !self.interrupt_enabled {
+            return;
+        }
+
+        self.interrupt_enabled = false;
+
+        // If the HALT bug was triggered right as interrupts were enabled (EI; HALT),
+        // the interrupt returns to the HALT instruction itself
+        if self.halt_bug {
+            self.halt_bug = false;
+            self.pc = self.pc.wrapping_sub(1);
+        }
+
+        self.bus.tick(2);
+
+        // PC is pushed like any other memory write, so it lands at the end of its cycle and is subject to OAM DMA
+        let pc = self.get_pc();
+        let mut sp = self.get_sp().wrapping_sub(1);
+        self.write_cycle(sp, pc.get_high_byte());
+
+        let inter = self.highest_pending_interrupt();
+        sp = sp.wrapping_sub(1);
+        self.write_cycle(sp, pc.get_low_byte());
+        self.set_sp(sp);
+
+        match inter {
+            Some(inter) => {
+                let mut if_reg = self.bus.peek(IF_REG);
+                match inter {
+                    Interrupts::VBLANK =>   { if_reg.clear_bit(0) },
+                    Interrupts::LCD_STAT => { if_reg.clear_bit(1) },
+                    Interrupts::TIMER =>    { if_reg.clear_bit(2) },
+                    Interrupts::SERIAL =>   { if_reg.clear_bit(3) },
+                    Interrupts::JOYPAD =>   { if_reg.clear_bit(4) },
+                }
+                self.bus.poke(IF_REG, if_reg);
+                self.set_pc(self.get_inter_vector(inter));
+            },
+            None => {
+                // Dispatch was cancelled by the push to IE
+                self.set_pc(0x0000);
+            }
+        }
+        self.bus.tick(1);
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::ppu::LY;
+    use crate::ppu::mode::FRAME_LEN;
+    use crate::test_rom;
+    use memory_bus::{BusEvent, FlatBus, InstrumentedBus};
+
+    type TestCpu = Cpu<InstrumentedBus<FlatBus>>;
+
+    const START_ADDR: u16 = 0x0200;
+    // LD A, $10; LDH (P1), A; STOP; INC B; JR -2
+    // Only the action buttons are selected in P1
+    const STOP_PROGRAM: [u8; 9] = [0x3E, 0x10, 0xE0, 0x00, 0x10, 0x00, 0x04, 0x18, 0xFE];
+    const VBLANK_BIT: u8 = 0;
+
+    // CPU on a flat RAM with the given program at START_ADDR
+    fn cpu_with_program(program: &[u8]) -> TestCpu {
+        let mut ram = FlatBus::new();
+        ram.load(START_ADDR, program);
+        let mut cpu = Cpu::with_bus(InstrumentedBus::new(ram));
+        cpu.set_pc(START_ADDR);
+        cpu
+    }
+
+    // Each logged read and write, along with the machine cycle it landed on (starting from 1)
+    fn accesses(cpu: &TestCpu) -> Vec<(u32, BusEvent)> {
+        let mut cycle = 0;
+        let mut events = Vec::new();
+        for event in cpu.get_bus().get_log() {
+            match *event {
+                BusEvent::Tick(cycles) => { cycle += cycles as u32 },
+                access => { events.push((cycle, access)) }
             }
+        }
+        events
+    }
+
+    // Total machine cycles logged
+    fn cycles(cpu: &TestCpu) -> u32 {
+        cpu.get_bus().get_log().iter().map(|event| match *event {
+            BusEvent::Tick(cycles) => cycles as u32,
+            _ => 0,
+        }).sum()
+    }
+
+    fn request_vblank(cpu: &mut TestCpu) {
+        cpu.get_bus_mut().get_inner_mut().load(IF_REG, &[1 << VBLANK_BIT]);
+        cpu.get_bus_mut().get_inner_mut().load(IE_REG, &[1 << VBLANK_BIT]);
+    }
+
+    #[test]
+    fn interrupt_dispatch_pushes_pc() {
+        // NOP
+        let mut cpu = cpu_with_program(&[0x00]);
+        cpu.set_sp(0xD000);
+        cpu.set_ime(true);
+        request_vblank(&mut cpu);
+        cpu.step();
+
+        assert_eq!(cpu.get_pc(), 0x0040);
+        assert_eq!(cpu.get_sp(), 0xCFFE);
+        assert!(!cpu.get_ime());
+        assert_eq!(cpu.get_bus().peek(IF_REG), 0x00);
+        // One cycle for NOP, then two idle cycles before the pushes
+        assert_eq!(accesses(&cpu), vec![
+            (1, BusEvent::Read(START_ADDR, 0x00)),
+            (4, BusEvent::Write(0xCFFF, 0x02)),
+            (5, BusEvent::Write(0xCFFE, 0x01)),
+        ]);
+        assert_eq!(cycles(&cpu), 1 + 5);
+    }
+
+    #[test]
+    fn interrupt_dispatch_cancelled_by_ie_push() {
+        // With SP at $0000, the high byte of PC ($02) is pushed onto IE, disabling VBLANK
+        let mut cpu = cpu_with_program(&[0x00]);
+        cpu.set_sp(0x0000);
+        cpu.set_ime(true);
+        request_vblank(&mut cpu);
+        cpu.step();
+
+        assert_eq!(cpu.get_pc(), 0x0000);
+        assert_eq!(cpu.get_bus().peek(IE_REG), 0x02);
+        // The interrupt wasn't serviced, so it's still requested
+        assert_eq!(cpu.get_bus().peek(IF_REG), 1 << VBLANK_BIT);
+        assert!(!cpu.get_ime());
+        assert_eq!(accesses(&cpu), vec![
+            (1, BusEvent::Read(START_ADDR, 0x00)),
+            (4, BusEvent::Write(IE_REG, 0x02)),
+            (5, BusEvent::Write(0xFFFE, 0x01)),
+        ]);
+        assert_eq!(cycles(&cpu), 1 + 5);
+    }
+
+    #[test]
+    fn halt_bug_reads_next_byte_twice() {
+        // HALT; INC A
+        let mut cpu = cpu_with_program(&[0x76, 0x3C]);
+        request_vblank(&mut cpu);
+        cpu.set_reg(Regs::A, 0);
+
+        cpu.step();
+        assert!(!cpu.halted);
+        cpu.step();
+        cpu.step();
+        assert_eq!(cpu.get_reg(Regs::A), 2);
+        assert_eq!(cpu.get_pc(), START_ADDR + 2);
+        assert_eq!(accesses(&cpu), vec![
+            (1, BusEvent::Read(START_ADDR, 0x76)),
+            (2, BusEvent::Read(START_ADDR + 1, 0x3C)),
+            (3, BusEvent::Read(START_ADDR + 1, 0x3C)),
+        ]);
+    }
+
+    #[test]
+    fn halt_without_ime_wakes_without_servicing() {
+        // HALT; INC A
+        let mut cpu = cpu_with_program(&[0x76, 0x3C]);
+        cpu.set_reg(Regs::A, 0);
 
-            self.write_ram(IF_REG, if_reg);
-            // TODO: I previously would iterate the PPU lcd by 3 cycles.
-            // I couldn't remember why that was done, and it was removed,
-            // but may be needed in future
+        cpu.step();
+        for _ in 0..10 {
+            cpu.step();
         }
+        assert!(cpu.halted);
+        assert_eq!(cpu.get_pc(), START_ADDR + 1);
+
+        request_vblank(&mut cpu);
+        cpu.step();
+        assert!(!cpu.halted);
+        cpu.step();
+        assert_eq!(cpu.get_reg(Regs::A), 1);
+        assert_eq!(cpu.get_pc(), START_ADDR + 2);
+        assert_eq!(cpu.get_bus().peek(IF_REG), 1 << VBLANK_BIT);
     }
 
-    /// ```
-    /// Enable interrupt
-    ///
-    /// Engages the specific interrupt type
-    ///
-    /// Input:
-    ///     Interrupt type (Interrupts)
-    /// ```
-    fn enable_interrupt(&mut self, inter: Interrupts) {
-        let mut if_reg = self.read_ram(IF_REG, None);
+    #[test]
+    fn halt_with_ime_services_interrupt() {
+        // HALT; INC A
+        let mut cpu = cpu_with_program(&[0x76, 0x3C]);
+        cpu.set_sp(0xD000);
+        cpu.set_ime(true);
+
+        cpu.step();
+        cpu.step();
+        assert!(cpu.halted);
+
+        request_vblank(&mut cpu);
+        cpu.step();
+        assert!(!cpu.halted);
+        assert_eq!(cpu.get_pc(), 0x0040);
+        // Returns to the instruction following HALT
+        assert_eq!(cpu.get_bus().peek(0xCFFE), 0x01);
+        assert_eq!(cpu.get_bus().peek(0xCFFF), 0x02);
+    }
 
-        match inter {
-            Interrupts::VBLANK =>   { if_reg.set_bit(0) },
-            Interrupts::LCD_STAT => { if_reg.set_bit(1) },
-            Interrupts::TIMER =>    { if_reg.set_bit(2) },
-            Interrupts::SERIAL =>   { if_reg.set_bit(3) },
-            Interrupts::JOYPAD =>   { if_reg.set_bit(4) },
+    #[test]
+    fn ei_takes_effect_after_next_instruction() {
+        // EI; NOP; NOP
+        let mut cpu = cpu_with_program(&[0xFB, 0x00, 0x00]);
+        cpu.set_sp(0xD000);
+        request_vblank(&mut cpu);
+
+        cpu.step();
+        assert_eq!(cpu.get_pc(), START_ADDR + 1);
+        cpu.step();
+        assert_eq!(cpu.get_pc(), 0x0040);
+        assert_eq!(cpu.get_bus().peek(0xCFFE), 0x02);
+    }
+
+    #[test]
+    fn ei_then_di_never_dispatches() {
+        // EI; DI; NOP
+        let mut cpu = cpu_with_program(&[0xFB, 0xF3, 0x00]);
+        cpu.set_sp(0xD000);
+        request_vblank(&mut cpu);
+
+        for _ in 0..3 {
+            cpu.step();
         }
+        assert_eq!(cpu.get_pc(), START_ADDR + 3);
+        assert!(!cpu.get_ime());
+        assert_eq!(cpu.get_bus().peek(IF_REG), 1 << VBLANK_BIT);
+    }
+
+    #[test]
+    fn ld_hl_n_writes_on_third_cycle() {
+        // LD (HL), $5A
+        let mut cpu = cpu_with_program(&[0x36, 0x5A]);
+        cpu.set_reg_16(Regs16::HL, 0xC000);
+        cpu.step();
+
+        assert_eq!(accesses(&cpu), vec![
+            (1, BusEvent::Read(START_ADDR, 0x36)),
+            (2, BusEvent::Read(START_ADDR + 1, 0x5A)),
+            (3, BusEvent::Write(0xC000, 0x5A)),
+        ]);
+        assert_eq!(cycles(&cpu), 3);
+    }
+
+    #[test]
+    fn cb_hl_reads_then_writes() {
+        // RLC (HL)
+        let mut cpu = cpu_with_program(&[0xCB, 0x06]);
+        cpu.set_reg_16(Regs16::HL, 0xC000);
+        cpu.get_bus_mut().get_inner_mut().load(0xC000, &[0x80]);
+        cpu.step();
+
+        assert_eq!(accesses(&cpu), vec![
+            (1, BusEvent::Read(START_ADDR, 0xCB)),
+            (2, BusEvent::Read(START_ADDR + 1, 0x06)),
+            (3, BusEvent::Read(0xC000, 0x80)),
+            (4, BusEvent::Write(0xC000, 0x01)),
+        ]);
+        assert_eq!(cycles(&cpu), 4);
+    }
+
+    #[test]
+    fn push_writes_after_internal_cycle() {
+        // PUSH BC
+        let mut cpu = cpu_with_program(&[0xC5]);
+        cpu.set_sp(0xD000);
+        cpu.set_reg_16(Regs16::BC, 0x1234);
+        cpu.step();
+
+        assert_eq!(accesses(&cpu), vec![
+            (1, BusEvent::Read(START_ADDR, 0xC5)),
+            (3, BusEvent::Write(0xCFFF, 0x12)),
+            (4, BusEvent::Write(0xCFFE, 0x34)),
+        ]);
+        assert_eq!(cycles(&cpu), 4);
+    }
+
+    #[test]
+    fn ld_a_nn_reads_on_last_cycle() {
+        // LD A, ($C000)
+        let mut cpu = cpu_with_program(&[0xFA, 0x00, 0xC0]);
+        cpu.get_bus_mut().get_inner_mut().load(0xC000, &[0x77]);
+        cpu.step();
+
+        assert_eq!(accesses(&cpu), vec![
+            (1, BusEvent::Read(START_ADDR, 0xFA)),
+            (2, BusEvent::Read(START_ADDR + 1, 0x00)),
+            (3, BusEvent::Read(START_ADDR + 2, 0xC0)),
+            (4, BusEvent::Read(0xC000, 0x77)),
+        ]);
+        assert_eq!(cpu.get_reg(Regs::A), 0x77);
+        assert_eq!(cycles(&cpu), 4);
+    }
 
-        self.write_ram(IF_REG, if_reg);
+    #[test]
+    fn stop_waits_for_selected_button() {
+        let mut gb = test_rom::boot(&test_rom::build(&STOP_PROGRAM), Some(Model::DMG));
+        for _ in 0..100 {
+            if gb.stopped {
+                break;
+            }
+            gb.tick();
+        }
+        assert!(gb.stopped);
+
+        // Nothing runs while stopped, but frames are still signalled so the frontend can poll input
+        let pc = gb.get_pc();
+        let b = gb.get_reg(Regs::B);
+        let ly = gb.read_ram(LY, None);
+        for _ in 0..1000 {
+            gb.tick();
+        }
+        assert_eq!(gb.get_pc(), pc);
+        assert_eq!(gb.get_reg(Regs::B), b);
+        assert_eq!(gb.read_ram(LY, None), ly);
+        let frames = (0..(FRAME_LEN / 4)).filter(|_| gb.tick()).count();
+        assert_eq!(frames, 1);
+
+        // Directions aren't selected, so they don't pull an input line low
+        gb.toggle_button(Buttons::Up, true);
+        gb.tick();
+        assert!(gb.stopped);
+
+        gb.toggle_button(Buttons::A, true);
+        gb.tick();
+        assert!(!gb.stopped);
+        gb.tick();
+        assert_eq!(gb.get_reg(Regs::B), b.wrapping_add(1));
+    }
+
+    #[test]
+    fn stop_skipped_while_button_held() {
+        let mut gb = test_rom::boot(&test_rom::build(&STOP_PROGRAM), Some(Model::DMG));
+        gb.toggle_button(Buttons::A, true);
+        let b = gb.get_reg(Regs::B);
+        for _ in 0..10 {
+            gb.tick();
+            assert!(!gb.stopped);
+        }
+        assert_ne!(gb.get_reg(Regs::B), b);
+    }
+
+    // Sends a byte over the serial port with the given SC value, as test ROMs print their results
+    fn serial_program(byte: u8, sc: u8) -> Vec<u8> {
+        // LD A, byte; LDH (SB), A; LD A, sc; LDH (SC), A; JR -2
+        test_rom::build(&[0x3E, byte, 0xE0, 0x01, 0x3E, sc, 0xE0, 0x02, 0x18, 0xFE])
+    }
+
+    #[test]
+    fn serial_text_captured() {
+        let mut gb = test_rom::boot(&serial_program(b'A', 0x81), Some(Model::DMG));
+        gb.set_serial_capture(true);
+        for _ in 0..10 {
+            gb.tick();
+        }
+        assert_eq!(gb.get_serial_text(), "A");
+    }
+
+    #[test]
+    fn serial_text_ignores_external_clock() {
+        // Without the internal clock bit, the byte is only sent once the other side clocks it
+        let mut gb = test_rom::boot(&serial_program(b'A', 0x80), Some(Model::DMG));
+        gb.set_serial_capture(true);
+        for _ in 0..10 {
+            gb.tick();
+        }
+        assert_eq!(gb.get_serial_text(), "");
     }
 }
@@ -5,7 +5,7 @@ pub const TIMA: u16 = 0xFF05; // Counter register
 pub const TMA: u16 = 0xFF06;  // Modulo register
 pub const TAC: u16 = 0xFF07;  // Control register
 
-const TAC_ENABLE_BIT: u8 = 3;
+const TAC_ENABLE_BIT: u8 = 2;
 const TIMA_COOLDOWN_OVERFLOW: u8 = 4;
 
 pub struct Timer {
@@ -77,6 +77,11 @@ impl Timer {
         interrupt
     }
 
+    // The boot ROM leaves the internal divider at a model specific value, which some games use to identify the hardware
+    pub fn set_div_counter(&mut self, div: u16) {
+        self.div = div;
+    }
+
     pub fn read_timer(&self, addr: u16) -> u8 {
         match addr {
             DIV => (self.div >> 8) as u8,
@@ -100,3 +105,21 @@ impl Timer {
         };
     }
 }
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn tima_counts_only_when_enabled() {
+        let mut timer = Timer::new();
+        // 262144 Hz, so TIMA increments every 4 machine cycles, but the timer is off
+        timer.write_timer(TAC, 0b001);
+        timer.tick(8);
+        assert_eq!(timer.read_timer(TIMA), 0);
+
+        timer.write_timer(TAC, 0b101);
+        timer.tick(8);
+        assert_eq!(timer.read_timer(TIMA), 2);
+    }
+}
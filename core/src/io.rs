@@ -83,6 +83,21 @@ impl IO {
         self.btns[i] = pressed;
     }
 
+    /// ```
+    /// Is pressed
+    ///
+    /// Whether the specified button is currently held
+    ///
+    /// Input:
+    ///     Button to check (Buttons)
+    ///
+    /// Output:
+    ///     Whether button is pressed (bool)
+    /// ```
+    pub fn is_pressed(&self, btn: Buttons) -> bool {
+        self.btns[btn.get_index()]
+    }
+
     /// ```
     /// Poll buttons
     ///
@@ -105,14 +120,50 @@ impl IO {
     ///     Encoding of currently pressed buttons (u8)
     /// ```
     pub fn read_btns(&self) -> u8 {
-        // AFAIK, the system can't ask for both values
+        // Unused upper bits read high, followed by the selection bits
+        let mut output = 0b1100_0000;
+        if !self.get_btn_keys {
+            output |= 0b0010_0000;
+        }
+        if !self.get_dir_keys {
+            output |= 0b0001_0000;
+        }
+
+        output | self.read_lines()
+    }
+
+    /// ```
+    /// Is line low
+    ///
+    /// Whether any of the joypad input lines are currently low
+    /// This is what wakes the CPU from STOP
+    ///
+    /// Output:
+    ///     Whether a button in a selected group is pressed (bool)
+    /// ```
+    pub fn is_line_low(&self) -> bool {
+        self.read_lines() != 0b1111
+    }
+
+    /// ```
+    /// Read lines
+    ///
+    /// Reads the four joypad input lines. If both groups are selected, a line is low
+    /// if a button from either group is pressed
+    ///
+    /// Output:
+    ///     State of the input lines, low if pressed (u8)
+    /// ```
+    fn read_lines(&self) -> u8 {
+        let mut lines = 0b1111;
         if self.get_btn_keys {
-            self.pack_btn_keys()
-        } else if self.get_dir_keys {
-            self.pack_dir_keys()
-        } else {
-            0
+            lines &= self.pack_btn_keys();
+        }
+        if self.get_dir_keys {
+            lines &= self.pack_dir_keys();
         }
+
+        lines
     }
 
     /// ```
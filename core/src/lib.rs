@@ -3,6 +3,11 @@ pub mod cartridge;
 pub mod cpu;
 pub mod debug;
 pub mod io;
+pub mod link;
 pub mod ppu;
+pub mod serial;
+pub mod sgb;
+#[cfg(test)]
+mod test_rom;
 pub mod utils;
 pub mod wram;
@@ -0,0 +1,211 @@
+// Link cable between two emulated Game Boys in the same process
+
+use crate::cpu::Cpu;
+use crate::serial::{SerialLink, SC};
+use crate::utils::ModifyBits;
+
+use std::cell::RefCell;
+use std::rc::Rc;
+
+const SC_START_BIT: u8 = 7;
+const SC_CLOCK_BIT: u8 = 0;
+
+// What each end of the cable is currently offering to the other
+#[derive(Copy, Clone, Default)]
+struct Port {
+    waiting: Option<u8>,    // Byte ready to be clocked out by the other side
+    incoming: Option<u8>,   // Byte clocked in by the other side, yet to be received
+}
+
+type Wire = Rc<RefCell<[Port; 2]>>;
+
+/// ```
+/// Pair link
+///
+/// One end of an in-process link cable
+/// ```
+struct PairLink {
+    wire: Wire,
+    side: usize,
+}
+
+impl SerialLink for PairLink {
+    fn exchange(&mut self, out: u8) -> Option<u8> {
+        let mut wire = self.wire.borrow_mut();
+        let other = &mut wire[1 - self.side];
+
+        // If the other side isn't waiting on our clock, there's nothing to shift in
+        match other.waiting.take() {
+            Some(reply) => {
+                other.incoming = Some(out);
+                Some(reply)
+            },
+            None => { Some(0xFF) }
+        }
+    }
+
+    fn poll_external(&mut self, out: u8) -> Option<u8> {
+        let mut wire = self.wire.borrow_mut();
+        let port = &mut wire[self.side];
+
+        match port.incoming.take() {
+            Some(byte) => { Some(byte) },
+            None => {
+                port.waiting = Some(out);
+                None
+            }
+        }
+    }
+}
+
+/// ```
+/// Linked pair
+///
+/// Two Game Boys connected via link cable, run in lockstep
+/// Whichever system is behind is always the next to run, so the pair stays deterministic
+/// ```
+pub struct LinkedPair {
+    gbs: [Box<Cpu>; 2],
+    wire: Wire,
+}
+
+impl LinkedPair {
+    /// ```
+    /// New
+    ///
+    /// Connects the two systems together
+    /// Systems are large, so they are kept on the heap rather than moved around by value
+    ///
+    /// Inputs:
+    ///     First system (Box<Cpu>)
+    ///     Second system (Box<Cpu>)
+    /// ```
+    pub fn new(first: Box<Cpu>, second: Box<Cpu>) -> LinkedPair {
+        let wire: Wire = Rc::new(RefCell::new([Port::default(); 2]));
+        let mut gbs = [first, second];
+        for (side, gb) in gbs.iter_mut().enumerate() {
+            gb.set_serial_link(Box::new(PairLink { wire: wire.clone(), side }));
+        }
+
+        LinkedPair {
+            gbs,
+            wire,
+        }
+    }
+
+    /// ```
+    /// Tick
+    ///
+    /// Runs one instruction on whichever system is furthest behind
+    ///
+    /// Output:
+    ///     Whether each system has a frame ready to render ([bool; 2])
+    /// ```
+    pub fn tick(&mut self) -> [bool; 2] {
+        let side = if self.gbs[1].get_elapsed_dots() < self.gbs[0].get_elapsed_dots() { 1 } else { 0 };
+        let mut draw_time = [false; 2];
+        draw_time[side] = self.gbs[side].tick();
+
+        // If this side has stopped waiting on an external clock (i.e. its transfer was cancelled),
+        // withdraw its offer so a stale byte isn't exchanged later
+        let sc = self.gbs[side].read_ram(SC, None);
+        if !sc.get_bit(SC_START_BIT) || sc.get_bit(SC_CLOCK_BIT) {
+            self.wire.borrow_mut()[side].waiting = None;
+        }
+
+        draw_time
+    }
+
+    /// ```
+    /// Get CPU
+    ///
+    /// Returns one of the linked systems
+    ///
+    /// Input:
+    ///     Which system, 0 or 1 (usize)
+    ///
+    /// Output:
+    ///     The specified system (&Cpu)
+    /// ```
+    pub fn get_cpu(&self, side: usize) -> &Cpu {
+        &self.gbs[side]
+    }
+
+    /// ```
+    /// Get CPU mut
+    ///
+    /// Returns one of the linked systems, for input or loading games
+    ///
+    /// Input:
+    ///     Which system, 0 or 1 (usize)
+    ///
+    /// Output:
+    ///     The specified system (&mut Cpu)
+    /// ```
+    pub fn get_cpu_mut(&mut self, side: usize) -> &mut Cpu {
+        &mut self.gbs[side]
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::cpu::IF_REG;
+    use crate::serial::SB;
+    use crate::test_rom;
+
+    const SERIAL_IF_BIT: u8 = 3;
+
+    // LD A, byte; LDH (SB), A; XOR A; LDH (IF), A; LD A, sc; LDH (SC), A; JR -2
+    fn transfer_program(byte: u8, sc: u8) -> Vec<u8> {
+        test_rom::build(&[0x3E, byte, 0xE0, 0x01, 0xAF, 0xE0, 0x0F, 0x3E, sc, 0xE0, 0x02, 0x18, 0xFE])
+    }
+
+    fn serial_requested(gb: &Cpu) -> bool {
+        gb.read_ram(IF_REG, None).get_bit(SERIAL_IF_BIT)
+    }
+
+    #[test]
+    fn exchanges_bytes_between_systems() {
+        let leader = Box::new(test_rom::boot(&transfer_program(0x42, 0x81), None));
+        let follower = Box::new(test_rom::boot(&transfer_program(0x99, 0x80), None));
+        let mut pair = LinkedPair::new(leader, follower);
+
+        for _ in 0..10 {
+            pair.tick();
+        }
+        assert!(!serial_requested(pair.get_cpu(0)));
+        assert!(!serial_requested(pair.get_cpu(1)));
+
+        let mut ticks = 0;
+        while !(serial_requested(pair.get_cpu(0)) && serial_requested(pair.get_cpu(1))) {
+            pair.tick();
+            ticks += 1;
+            assert!(ticks < 10_000, "Transfer never completed");
+        }
+
+        assert_eq!(pair.get_cpu(0).read_ram(SB, None), 0x99);
+        assert_eq!(pair.get_cpu(1).read_ram(SB, None), 0x42);
+        assert!(!pair.get_cpu(0).read_ram(SC, None).get_bit(SC_START_BIT));
+        assert!(!pair.get_cpu(1).read_ram(SC, None).get_bit(SC_START_BIT));
+    }
+
+    #[test]
+    fn transfer_completes_after_1024_cycles() {
+        let leader = Box::new(test_rom::boot(&transfer_program(0x42, 0x81), None));
+        let follower = Box::new(test_rom::boot(&transfer_program(0x99, 0x80), None));
+        let mut pair = LinkedPair::new(leader, follower);
+
+        // Run until the leader's transfer starts, then count the dots until it ends
+        while !pair.get_cpu(0).read_ram(SC, None).get_bit(SC_START_BIT) {
+            pair.tick();
+        }
+        let start = pair.get_cpu(0).get_elapsed_dots();
+        while !serial_requested(pair.get_cpu(0)) {
+            pair.tick();
+        }
+        let m_cycles = (pair.get_cpu(0).get_elapsed_dots() - start) / 4;
+        // JR takes 3 M-cycles, so the last instruction may overshoot
+        assert!((1024..1027).contains(&m_cycles), "Transfer took {} M-cycles", m_cycles);
+    }
+}
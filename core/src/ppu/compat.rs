@@ -0,0 +1,181 @@
+// CGB compatibility palettes
+// When a DMG game is run on a CGB, the boot ROM colorizes it with a palette chosen from the game's title
+
+use super::palette::{gbc2rgba, DMG_PAL_SIZE};
+use crate::utils::COLOR_CHANNELS;
+
+// =============
+// = Constants =
+// =============
+
+// Sum of the title bytes of each recognized game. Some checksums are shared by multiple games,
+// those after the first duplicate are only matched if the fourth letter of the title also matches
+const TITLE_CHECKSUMS: [u8; 94] = [
+    0x00, 0x88, 0x16, 0x36, 0xD1, 0xDB, 0xF2, 0x3C, 0x8C, 0x92, 0x3D, 0x5C, 0x58, 0xC9, 0x3E, 0x70,
+    0x1D, 0x59, 0x69, 0x19, 0x35, 0xA8, 0x14, 0xAA, 0x75, 0x95, 0x99, 0x34, 0x6F, 0x15, 0xFF, 0x97,
+    0x4B, 0x90, 0x17, 0x10, 0x39, 0xF7, 0xF6, 0xA2, 0x49, 0x4E, 0x43, 0x68, 0xE0, 0x8B, 0xF0, 0xCE,
+    0x0C, 0x29, 0xE8, 0xB7, 0x86, 0x9A, 0x52, 0x01, 0x9D, 0x71, 0x9C, 0xBD, 0x5D, 0x6D, 0x67, 0x3F,
+    0x6B,
+    // Duplicates
+    0xB3, 0x46, 0x28, 0xA5, 0xC6, 0xD3, 0x27, 0x61, 0x18, 0x66, 0x6A, 0xBF, 0x0D, 0xF4, 0xB3, 0x46,
+    0x28, 0xA5, 0xC6, 0xD3, 0x27, 0x61, 0x18, 0x66, 0x6A, 0xBF, 0x0D, 0xF4, 0xB3,
+];
+const FIRST_DUPLICATE: usize = 65;
+const DUPLICATE_LETTERS: &[u8; 29] = b"BEFAARBEKEK R-URAR INAILICE R";
+
+// Which palette combination each entry of TITLE_CHECKSUMS uses
+const PALETTE_IDS: [u8; 94] = [
+    0,  4,  5,  35, 34, 3,  31, 15, 10, 5,  19, 36, 7,  37, 30, 44,
+    21, 32, 31, 20, 5,  33, 13, 14, 5,  29, 5,  18, 9,  3,  2,  26,
+    25, 25, 41, 42, 26, 45, 42, 45, 36, 38, 26, 42, 30, 41, 34, 34,
+    5,  42, 6,  5,  33, 25, 42, 42, 40, 2,  16, 25, 42, 42, 5,  0,
+    39,
+    // Duplicates
+    36, 22, 25, 6,  32, 12, 36, 11, 39, 18, 39, 24, 31, 50, 17, 46,
+    6,  27, 0,  47, 41, 41, 0,  0,  19, 34, 23, 18, 29,
+];
+
+// Index into COLORS of the first color of the OBJ0, OBJ1 and BG palettes of each combination
+// Most start on a palette boundary, but a few are offset by a color
+const COMBINATIONS: [[usize; 3]; 51] = [
+    [16, 16, 116], [72, 72, 72], [80, 80, 80], [96, 96, 96], [36, 36, 36], [0, 0, 0], [108, 108, 108], [20, 20, 20],
+    [48, 48, 48], [104, 104, 104], [64, 32, 32], [16, 112, 112], [16, 8, 8], [12, 16, 16], [16, 116, 116], [112, 16, 112],
+    [8, 68, 8], [64, 64, 32], [16, 16, 28], [16, 16, 72], [16, 16, 80], [76, 76, 36], [15, 15, 44], [68, 68, 8],
+    [16, 16, 8], [16, 16, 12], [112, 112, 0], [12, 12, 0], [0, 0, 4], [72, 88, 72], [80, 88, 80], [96, 88, 96],
+    [64, 88, 32], [68, 16, 52], [111, 0, 56], [111, 16, 60], [76, 88, 36], [64, 112, 40], [16, 92, 112], [68, 88, 8],
+    [16, 0, 8], [16, 112, 12], [112, 12, 0], [12, 112, 16], [84, 112, 16], [12, 112, 0], [100, 12, 112], [0, 112, 32],
+    [16, 12, 112], [112, 12, 24], [16, 112, 116],
+];
+
+// CGB 15-bit colors, four to a palette
+const COLORS: [u16; 120] = [
+    0x7FFF, 0x32BF, 0x00D0, 0x0000,
+    0x639F, 0x4279, 0x15B0, 0x04CB,
+    0x7FFF, 0x6E31, 0x454A, 0x0000,
+    0x7FFF, 0x1BEF, 0x0200, 0x0000,
+    0x7FFF, 0x421F, 0x1CF2, 0x0000,
+    0x7FFF, 0x5294, 0x294A, 0x0000,
+    0x7FFF, 0x03FF, 0x012F, 0x0000,
+    0x7FFF, 0x03EF, 0x01D6, 0x0000,
+    0x7FFF, 0x42B5, 0x3DC8, 0x0000,
+    0x7E74, 0x03FF, 0x0180, 0x0000,
+    0x67FF, 0x77AC, 0x1A13, 0x2D6B,
+    0x7ED6, 0x4BFF, 0x2175, 0x0000,
+    0x53FF, 0x4A5F, 0x7E52, 0x0000,
+    0x4FFF, 0x7ED2, 0x3A4C, 0x1CE0,
+    0x03ED, 0x7FFF, 0x255F, 0x0000,
+    0x036A, 0x021F, 0x03FF, 0x7FFF,
+    0x7FFF, 0x01DF, 0x0112, 0x0000,
+    0x231F, 0x035F, 0x00F2, 0x0009,
+    0x7FFF, 0x03EA, 0x011F, 0x0000,
+    0x299F, 0x001A, 0x000C, 0x0000,
+    0x7FFF, 0x027F, 0x001F, 0x0000,
+    0x7FFF, 0x03E0, 0x0206, 0x0120,
+    0x7FFF, 0x7EEB, 0x001F, 0x7C00,
+    0x7FFF, 0x3FFF, 0x7E00, 0x001F,
+    0x7FFF, 0x03FF, 0x001F, 0x0000,
+    0x03FF, 0x001F, 0x000C, 0x0000,
+    0x7FFF, 0x033F, 0x0193, 0x0000,
+    0x0000, 0x4200, 0x037F, 0x7FFF,
+    0x7FFF, 0x7E8C, 0x7C00, 0x0000,
+    0x7FFF, 0x1BEF, 0x6180, 0x0000,
+];
+
+#[derive(Copy, Clone)]
+pub struct CompatPalette {
+    pub bg: [[u8; COLOR_CHANNELS]; DMG_PAL_SIZE],
+    pub obj0: [[u8; COLOR_CHANNELS]; DMG_PAL_SIZE],
+    pub obj1: [[u8; COLOR_CHANNELS]; DMG_PAL_SIZE],
+}
+
+/// ```
+/// Get compatibility palette
+///
+/// Looks up the palette the CGB boot ROM would choose for a DMG game
+///
+/// Inputs:
+///     Title checksum, or None if not a Nintendo game (Option<u8>)
+///     Fourth letter of the title (u8)
+///
+/// Output:
+///     BG and OBJ palettes for the game (CompatPalette)
+/// ```
+pub fn get_compat_palette(checksum: Option<u8>, fourth_letter: u8) -> CompatPalette {
+    // Unrecognized games use the first combination
+    let id = checksum.and_then(|checksum| {
+        (0..TITLE_CHECKSUMS.len()).find(|&i| {
+            TITLE_CHECKSUMS[i] == checksum && (i < FIRST_DUPLICATE || DUPLICATE_LETTERS[i - FIRST_DUPLICATE] == fourth_letter)
+        })
+    }).map_or(0, |i| PALETTE_IDS[i] as usize);
+
+    let [obj0, obj1, bg] = COMBINATIONS[id];
+    CompatPalette {
+        bg: get_colors(bg),
+        obj0: get_colors(obj0),
+        obj1: get_colors(obj1),
+    }
+}
+
+/// ```
+/// Get colors
+///
+/// Converts four consecutive colors from the table into RGBA
+///
+/// Input:
+///     Index of first color (usize)
+///
+/// Output:
+///     RGBA values for palette ([4x[RGBA u8 values]])
+/// ```
+fn get_colors(start: usize) -> [[u8; COLOR_CHANNELS]; DMG_PAL_SIZE] {
+    let mut colors = [[0; COLOR_CHANNELS]; DMG_PAL_SIZE];
+    for (i, color) in colors.iter_mut().enumerate() {
+        let gbc = COLORS[start + i];
+        *color = gbc2rgba((gbc & 0xFF) as u8, (gbc >> 8) as u8);
+    }
+    colors
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn rgba(colors: [u16; DMG_PAL_SIZE]) -> [[u8; COLOR_CHANNELS]; DMG_PAL_SIZE] {
+        let mut rgba = [[0; COLOR_CHANNELS]; DMG_PAL_SIZE];
+        for (i, color) in colors.iter().enumerate() {
+            rgba[i] = gbc2rgba(*color as u8, (*color >> 8) as u8);
+        }
+        rgba
+    }
+
+    #[test]
+    fn pokemon_red() {
+        let pal = get_compat_palette(Some(0x14), b'E');
+        assert_eq!(pal.bg, rgba([0x7FFF, 0x421F, 0x1CF2, 0x0000]));
+        assert_eq!(pal.obj0, rgba([0x7FFF, 0x1BEF, 0x0200, 0x0000]));
+        assert_eq!(pal.obj1, rgba([0x7FFF, 0x421F, 0x1CF2, 0x0000]));
+    }
+
+    #[test]
+    fn unknown_title_uses_first_combination() {
+        let first = CompatPalette {
+            bg: get_colors(COMBINATIONS[0][2]),
+            obj0: get_colors(COMBINATIONS[0][0]),
+            obj1: get_colors(COMBINATIONS[0][1]),
+        };
+        for pal in [get_compat_palette(None, b'A'), get_compat_palette(Some(0x02), b'A')].iter() {
+            assert_eq!(pal.bg, first.bg);
+            assert_eq!(pal.obj0, first.obj0);
+            assert_eq!(pal.obj1, first.obj1);
+        }
+        assert_eq!(first.bg, rgba([0x7FFF, 0x1BEF, 0x6180, 0x0000]));
+    }
+
+    #[test]
+    fn duplicate_checksums_use_fourth_letter() {
+        // $46 is shared by several games, told apart by the fourth letter of the title
+        assert_eq!(get_compat_palette(Some(0x46), b'E').bg, get_colors(COMBINATIONS[22][2]));
+        assert_eq!(get_compat_palette(Some(0x46), b'R').bg, get_colors(COMBINATIONS[46][2]));
+        assert_eq!(get_compat_palette(Some(0x46), b'Z').bg, get_colors(COMBINATIONS[0][2]));
+    }
+}
@@ -1,5 +1,6 @@
 pub mod mode;
 pub mod palette;
+mod compat;
 mod map;
 mod sprite;
 mod tile;
@@ -59,6 +60,8 @@ const OAM_SPR_NUM: usize = 40;
 const SPR_PER_LINE: usize = 10;
 const CGB_BG_PAL_DATA_SIZE: usize = 64; // 8 palettes, 4 colors per palette, 2 bytes per color
 const CGB_SPR_PAL_DATA_SIZE: usize = 64;
+const WX_OFFSET: isize = 7;
+const WX_MAX: u8 = 166;
 
 // Register bit constants
 const BG_DISP_BIT: u8           = 0;
@@ -78,6 +81,13 @@ const VBLANK_INTERRUPT_BIT: u8  = 4;
 const OAM_INTERRUPT_BIT: u8     = 5;
 const LYC_LY_INTERRUPT_BIT: u8  = 6;
 
+// Background/window information kept for each pixel of a scanline, used to resolve sprite priority
+#[derive(Copy, Clone, Default)]
+struct BgPixel {
+    color: u8,
+    priority: bool,
+}
+
 pub struct PpuUpdateResult {
     pub lcd_result: LcdResults,
     pub interrupt: bool,
@@ -87,14 +97,19 @@ pub struct PPU {
     vram_bank: usize,
     io: [u8; IO_SIZE],
     screen_buffer: [u8; DISP_SIZE],
+    shade_buffer: [u8; SCREEN_WIDTH * SCREEN_HEIGHT],
     tiles: [Tile; VRAM_BANK_NUM * TILE_NUM],
     tile_maps: [Map; VRAM_BANK_NUM * TILE_MAP_SIZE],
     oam: [Sprite; OAM_SPR_NUM],
-    last_wndw_line: Option<u8>,
+    wndw_line: u8,
+    wndw_y_triggered: bool,
     cgb_bg_pal_data: [u8; CGB_BG_PAL_DATA_SIZE],
     cgb_spr_pal_data: [u8; CGB_SPR_PAL_DATA_SIZE],
     lcd_mode: Lcd,
     palette: Palette,
+    strict_vram_access: bool,
+    blank_frame: bool,
+    stat_irq_line: bool,
 }
 
 impl Default for PPU {
@@ -112,14 +127,19 @@ impl PPU {
             vram_bank: 0,
             io: [0; IO_SIZE],
             screen_buffer: [0; DISP_SIZE],
+            shade_buffer: [0; SCREEN_WIDTH * SCREEN_HEIGHT],
             tiles: [Tile::new(); VRAM_BANK_NUM * TILE_NUM],
             tile_maps: [Map::new(); VRAM_BANK_NUM * TILE_MAP_SIZE],
             oam: [Sprite::new(); OAM_SPR_NUM],
-            last_wndw_line: None,
+            wndw_line: 0,
+            wndw_y_triggered: false,
             cgb_bg_pal_data: [0; CGB_BG_PAL_DATA_SIZE],
             cgb_spr_pal_data: [0; CGB_SPR_PAL_DATA_SIZE],
             lcd_mode: Lcd::new(),
             palette: Palette::new(),
+            strict_vram_access: false,
+            blank_frame: false,
+            stat_irq_line: false,
         }
     }
 
@@ -134,39 +154,24 @@ impl PPU {
     ///     System mode (GB)
     /// ```
     pub fn write_vram(&mut self, addr: u16, val: u8, mode: GB) {
-        // TODO: These limitations need to eventually be supported,
-        // but due to my poor LCD timer, cause issues due to inaccuracies
-        // let lcd_mode = self.lcd_mode.get_mode();
+        if self.is_access_blocked(addr, mode) {
+            return;
+        }
 
         match addr {
             OAM_START..=OAM_END => {
-                // During LCD modes 2 and 3, cannot access OAM
-                // if lcd_mode == LcdModeType::OAMReadMode || lcd_mode == LcdModeType::VRAMReadMode {
-                //     return;
-                // }
-
                 let relative_addr = addr - OAM_START;
                 let spr_num = relative_addr / OAM_BYTE_SIZE;
                 let byte_num = relative_addr % OAM_BYTE_SIZE;
                 self.oam[spr_num as usize].set_byte(byte_num, val, mode);
             },
             TILE_SET..=TILE_SET_END => {
-                // During LCD mode 3, cannot access VRAM
-                // if lcd_mode == LcdModeType::VRAMReadMode {
-                //     return;
-                // }
-
                 let offset = addr - TILE_SET;
                 let tile_num = (offset / TILE_BYTES) + (self.vram_bank * TILE_NUM) as u16;
                 let byte_num = offset % TILE_BYTES;
                 self.tiles[tile_num as usize].set_byte(byte_num, val);
             },
             TILE_MAP..=TILE_MAP_END => {
-                // During LCD mode 3, cannot access VRAM
-                // if lcd_mode == LcdModeType::VRAMReadMode {
-                //     return;
-                // }
-
                 let map_addr = (addr - TILE_MAP) as usize;
                 if self.vram_bank == 0 {
                     self.tile_maps[map_addr].set_tile_num(val);
@@ -177,6 +182,10 @@ impl PPU {
             },
             IO_START..=IO_END => {
                 match addr {
+                    LCDC => {
+                        self.write_io(LCDC, val);
+                        self.set_lcd_enabled(val.get_bit(LCD_DISP_BIT));
+                    },
                     STAT => {
                         let mask = val & 0b1111_1000;
                         let stat = self.read_io(STAT);
@@ -184,11 +193,6 @@ impl PPU {
                     },
                     BGPD => {
                         if mode == GB::CGB {
-                            // During LCD mode 3, cannot edit palette data
-                            // if lcd_mode == LcdModeType::VRAMReadMode {
-                            //     return;
-                            // }
-
                             self.write_cgb_bg_color(val);
                         } else {
                             self.write_io(addr, val);
@@ -196,11 +200,6 @@ impl PPU {
                     },
                     OBPD => {
                         if mode == GB::CGB {
-                            // During LCD mode 3, cannot edit palette data
-                            // if lcd_mode == LcdModeType::VRAMReadMode {
-                            //     return;
-                            // }
-
                             self.write_cgb_spr_color(val);
                         } else {
                             self.write_io(addr, val);
@@ -222,6 +221,37 @@ impl PPU {
         }
     }
 
+    /// ```
+    /// Write OAM via DMA
+    ///
+    /// Writes a byte into OAM on behalf of an OAM DMA transfer
+    /// DMA owns the OAM bus, so this ignores the LCD mode access restrictions
+    ///
+    /// Inputs:
+    ///     Address to write to (u16)
+    ///     Value to write (u8)
+    ///     System mode (GB)
+    /// ```
+    pub fn dma_write_oam(&mut self, addr: u16, val: u8, mode: GB) {
+        let relative_addr = addr - OAM_START;
+        let spr_num = relative_addr / OAM_BYTE_SIZE;
+        let byte_num = relative_addr % OAM_BYTE_SIZE;
+        self.oam[spr_num as usize].set_byte(byte_num, val, mode);
+    }
+
+    /// ```
+    /// Set strict VRAM access
+    ///
+    /// Toggles whether the CPU is locked out of OAM, VRAM and CGB palette RAM
+    /// while the LCD is using them, as on real hardware
+    ///
+    /// Input:
+    ///     Whether to enforce access restrictions (bool)
+    /// ```
+    pub fn set_strict_vram_access(&mut self, strict: bool) {
+        self.strict_vram_access = strict;
+    }
+
     /// ```
     /// Read VRAM
     ///
@@ -236,6 +266,11 @@ impl PPU {
     ///     Value at given address (u8)
     /// ```
     pub fn read_vram(&self, addr: u16, bank_override: Option<u16>, mode: GB) -> u8 {
+        // Blocked reads see an open bus. Debugger reads always pass a bank override, so they can still peek
+        if bank_override.is_none() && self.is_access_blocked(addr, mode) {
+            return 0xFF;
+        }
+
         let bank = if let Some(b) = bank_override {
             b as usize
         } else {
@@ -265,7 +300,10 @@ impl PPU {
                 }
             },
             IO_START..=IO_END => {
-                if mode == GB::CGB {
+                if addr == STAT {
+                    // Bit 7 is unused, and always reads high
+                    self.read_io(STAT) | 0x80
+                } else if mode == GB::CGB {
                     match addr {
                         BGPD => {
                             self.read_cgb_bg_color()
@@ -291,39 +329,65 @@ impl PPU {
         }
     }
 
-    pub fn update(&mut self, cycles: u8) -> PpuUpdateResult {
+    /// ```
+    /// Update
+    ///
+    /// Advances the LCD by the given number of dots
+    ///
+    /// Input:
+    ///     Number of dots to advance (u8)
+    ///
+    /// Output:
+    ///     Results of the LCD update (PpuUpdateResult)
+    /// ```
+    pub fn update(&mut self, dots: u8) -> PpuUpdateResult {
         let old_mode = self.lcd_mode.get_mode();
-        let lcd_result = self.lcd_mode.lcd_step(cycles);
-        let mut interrupt = self.set_ly();
+        let lcd_result = self.lcd_mode.lcd_step(dots);
 
-        // Trigger interrupt if
-        // - Mode has changed
-        // - Interrupt for that mode is enabled
-        let mut stat = self.read_io(STAT);
+        // Nothing on the LCD changes while it is off, and no STAT interrupts can occur
+        if !self.lcd_mode.is_enabled() {
+            self.stat_irq_line = false;
+            return PpuUpdateResult{ lcd_result, interrupt: false };
+        }
+
+        self.set_ly();
+        self.check_lyc();
+
+        // The blanked frame following the LCD being re-enabled is over once VBLANK ends
         let mode = self.lcd_mode.get_mode();
-        if old_mode != mode {
-            match mode {
-                LcdModeType::HBLANK => {
-                    interrupt |= stat.get_bit(HBLANK_INTERRUPT_BIT);
-                },
-                LcdModeType::VBLANK => {
-                    interrupt |= stat.get_bit(VBLANK_INTERRUPT_BIT);
-                },
-                LcdModeType::VRAMReadMode => {
-                    interrupt |= stat.get_bit(OAM_INTERRUPT_BIT);
-                },
-                _ => ()
-            }
+        if old_mode == LcdModeType::VBLANK && mode != LcdModeType::VBLANK {
+            self.blank_frame = false;
         }
 
         // Update the STAT register to match our new LCD mode
+        let mut stat = self.read_io(STAT);
         stat &= 0b1111_1100;
         stat |= mode.get_idx();
         self.write_io(STAT, stat);
 
+        let interrupt = self.update_stat_line();
         PpuUpdateResult{ lcd_result, interrupt }
     }
 
+    /// ```
+    /// Skip to VBLANK line
+    ///
+    /// Moves the LCD to the start of the given VBLANK scanline, updating LY and STAT to match
+    ///
+    /// Input:
+    ///     Scanline to move to (u8)
+    /// ```
+    pub fn skip_to_vblank_line(&mut self, line: u8) {
+        self.lcd_mode.skip_to_vblank_line(line);
+        self.set_ly();
+        self.check_lyc();
+
+        let mut stat = self.read_io(STAT);
+        stat &= 0b1111_1100;
+        stat |= LcdModeType::VBLANK.get_idx();
+        self.write_io(STAT, stat);
+    }
+
     pub fn get_lcd_mode(&self) -> LcdModeType {
         self.lcd_mode.get_mode()
     }
@@ -332,36 +396,85 @@ impl PPU {
     /// Set LY register
     ///
     /// Sets the value at the LY RAM address
-    ///
-    /// Output:
-    ///     Whether values in LY and LYC registers are equal (bool)
     /// ```
-    fn set_ly(&mut self) -> bool {
+    fn set_ly(&mut self) {
         let line = self.lcd_mode.get_scanline();
         let old_ly = self.read_io(LY);
         if old_ly != line {
             // If we are in a new frame, reset window layer line
             if line == 0 {
-                self.last_wndw_line = None;
+                self.reset_wndw();
             }
 
             self.write_io(LY, line);
+        }
+    }
 
-            let mut stat = self.read_io(STAT);
-            if self.read_io(LY) == self.read_io(LYC) {
-                // If LY and LYC are equal:
-                // - Set coincidence bit in STAT register
-                // - Trigger LCDC status interrupt if enabled
-                stat.set_bit(LYC_LY_FLAG_BIT);
-                self.write_io(STAT, stat);
-                return stat.get_bit(LYC_LY_INTERRUPT_BIT);
-            } else {
-                stat.clear_bit(LYC_LY_FLAG_BIT);
-                self.write_io(STAT, stat);
-            }
+    /// ```
+    /// Check LYC
+    ///
+    /// Compares LY against LYC, updating the coincidence flag in STAT
+    /// ```
+    fn check_lyc(&mut self) {
+        let mut stat = self.read_io(STAT);
+        let coincidence = self.read_io(LY) == self.read_io(LYC);
+        stat.write_bit(LYC_LY_FLAG_BIT, coincidence);
+        self.write_io(STAT, stat);
+    }
+
+    /// ```
+    /// Update STAT line
+    ///
+    /// The STAT interrupt is raised on the rising edge of a single internal signal,
+    /// which is the OR of every enabled STAT source (HBLANK, VBLANK, OAM, LY=LYC).
+    /// While any source holds the line high, other sources becoming active will not
+    /// trigger another interrupt ("STAT IRQ blocking").
+    ///
+    /// Output:
+    ///     Whether the STAT interrupt should be requested (bool)
+    /// ```
+    fn update_stat_line(&mut self) -> bool {
+        let stat = self.read_io(STAT);
+        let line = match self.lcd_mode.get_mode() {
+            LcdModeType::HBLANK =>       stat.get_bit(HBLANK_INTERRUPT_BIT),
+            LcdModeType::VBLANK =>       stat.get_bit(VBLANK_INTERRUPT_BIT),
+            LcdModeType::OAMReadMode =>  stat.get_bit(OAM_INTERRUPT_BIT),
+            LcdModeType::VRAMReadMode => false,
+        } || (stat.get_bit(LYC_LY_FLAG_BIT) && stat.get_bit(LYC_LY_INTERRUPT_BIT));
+
+        let rising_edge = line && !self.stat_irq_line;
+        self.stat_irq_line = line;
+        rising_edge
+    }
+
+    /// ```
+    /// Set LCD enabled
+    ///
+    /// Handles the LCD being turned on or off via LCDC bit 7
+    /// While off, LY reads 0 and STAT reports mode 0
+    /// When turned back on, timing restarts from line 0 and the first frame is blank
+    ///
+    /// Input:
+    ///     Whether the LCD is now on (bool)
+    /// ```
+    fn set_lcd_enabled(&mut self, enabled: bool) {
+        if enabled == self.lcd_mode.is_enabled() {
+            return;
+        }
+
+        if enabled {
+            self.lcd_mode.enable();
+            self.blank_frame = true;
+            self.reset_wndw();
+        } else {
+            self.lcd_mode.disable();
+            self.write_io(LY, 0);
         }
 
-        false
+        let mut stat = self.read_io(STAT);
+        stat &= 0b1111_1100;
+        stat |= self.lcd_mode.get_mode().get_idx();
+        self.write_io(STAT, stat);
     }
 
     /// ```
@@ -376,23 +489,32 @@ impl PPU {
         // Render current scanline
         let line = self.read_io(LY);
         let mut pixel_row = [0xFF; SCREEN_WIDTH * COLOR_CHANNELS];
+        let mut bg_line = [BgPixel::default(); SCREEN_WIDTH];
+        let mut shade_row = [0; SCREEN_WIDTH];
 
         if self.is_bkgd_dspl(mode) {
-            self.render_background_line(&mut pixel_row, line, mode);
+            self.render_background_line(&mut pixel_row, &mut bg_line, &mut shade_row, line, mode);
         }
 
-        if self.is_wndw_dspl() {
-            self.render_wndw_line(&mut pixel_row, line, mode);
+        // Once LY has matched WY, the window is eligible to be drawn for the rest of the frame
+        if line == self.read_io(WY) {
+            self.wndw_y_triggered = true;
+        }
+
+        if self.is_wndw_dspl(mode) {
+            self.render_wndw_line(&mut pixel_row, &mut bg_line, &mut shade_row, mode);
         }
 
         if self.is_sprt_dspl() {
-            self.render_sprite_line(&mut pixel_row, line, mode);
+            self.render_sprite_line(&mut pixel_row, &bg_line, &mut shade_row, line, mode);
         }
 
         // Copy this line of pixels into overall screen buffer
         let start_index = line as usize * (SCREEN_WIDTH * COLOR_CHANNELS);
         let end_index = (line + 1) as usize * (SCREEN_WIDTH * COLOR_CHANNELS);
         self.screen_buffer[start_index..end_index].copy_from_slice(&pixel_row);
+        let start_index = line as usize * SCREEN_WIDTH;
+        self.shade_buffer[start_index..(start_index + SCREEN_WIDTH)].copy_from_slice(&shade_row);
     }
 
     /// ```
@@ -405,12 +527,29 @@ impl PPU {
     /// ```
     pub fn render_screen(&self) -> [u8; DISP_SIZE] {
         let mut map_array = [0xFF; DISP_SIZE];
-        if self.is_lcd_dspl() {
+        if self.is_lcd_dspl() && !self.blank_frame {
             map_array.copy_from_slice(&self.screen_buffer);
         }
         map_array
     }
 
+    /// ```
+    /// Render shades
+    ///
+    /// Gets which of the four DMG shades each pixel of the current screen is,
+    /// which the Super Game Boy colorizes itself
+    ///
+    /// Output:
+    ///     Shade of each pixel ([u8])
+    /// ```
+    pub fn render_shades(&self) -> [u8; SCREEN_WIDTH * SCREEN_HEIGHT] {
+        if self.is_lcd_dspl() && !self.blank_frame {
+            self.shade_buffer
+        } else {
+            [0; SCREEN_WIDTH * SCREEN_HEIGHT]
+        }
+    }
+
     /// ```
     /// Set system palette
     ///
@@ -423,6 +562,19 @@ impl PPU {
         self.palette.set_sys_pal(pal);
     }
 
+    /// ```
+    /// Set compatibility palette
+    ///
+    /// Use the palette the CGB boot ROM would choose for the game
+    ///
+    /// Inputs:
+    ///     Title checksum, or None if not a Nintendo game (Option<u8>)
+    ///     Fourth letter of the title (u8)
+    /// ```
+    pub fn set_compat_pal(&mut self, checksum: Option<u8>, fourth_letter: u8) {
+        self.palette.set_compat_pal(checksum, fourth_letter);
+    }
+
     // ===================
     // = Private methods =
     // ===================
@@ -434,10 +586,12 @@ impl PPU {
     ///
     /// Inputs:
     ///     Array to load pixel data into (&[u8])
+    ///     Per-pixel background info, for sprite priority (&[BgPixel])
+    ///     Array to load DMG shades into (&[u8])
     ///     Scanline to render (u8)
     ///     Hardware type (GB)
     /// ```
-    fn render_background_line(&self, pixel_row: &mut [u8], line: u8, mode: GB) {
+    fn render_background_line(&self, pixel_row: &mut [u8], bg_line: &mut [BgPixel], shade_row: &mut [u8], line: u8, mode: GB) {
         // TODO: This is not ideal. Someday, I'd like to not have this variable if we aren't DMG
         let dmg_pal = self.palette.get_bg_pal();
         let pal_indices = self.get_dmg_bg_indices();
@@ -485,8 +639,10 @@ impl PPU {
                 let pal_indices = self.get_cgb_bg_indices(tile_data.get_pal_num());
                 gbc2rgba(pal_indices[2 * pixel], pal_indices[2 * pixel + 1])
             } else {
+                shade_row[x] = pal_indices[pixel];
                 dmg_pal[pal_indices[pixel] as usize]
             };
+            bg_line[x] = BgPixel { color: pixel as u8, priority: tile_data.is_bg_priority() };
 
             for i in 0..COLOR_CHANNELS {
                 pixel_row[COLOR_CHANNELS * x + i] = color[i];
@@ -497,19 +653,23 @@ impl PPU {
     /// ```
     /// Render Window Line
     ///
-    /// Renders the given scanline of the window layer
+    /// Renders the next line of the window layer onto the current scanline
+    ///
+    /// The window keeps its own line counter, which only advances on scanlines where
+    /// the window was actually drawn. If a game hides the window mid-frame (or moves it
+    /// offscreen) and later shows it again, it resumes from where it left off rather
+    /// than skipping ahead to match LY.
     ///
     /// Inputs:
     ///     Array to load pixel data into (&[u8])
-    ///     Scanline to render (u8)
+    ///     Per-pixel background info, for sprite priority (&[BgPixel])
+    ///     Array to load DMG shades into (&[u8])
+    ///     Hardware type (GB)
     /// ```
-    fn render_wndw_line(&mut self, pixel_row: &mut [u8], line: u8, mode: GB) {
-        let wndw_coords = self.get_wndw_coords();
-        // See below for why this is needed
-        let line = if self.last_wndw_line.is_none() { line } else { self.last_wndw_line.unwrap() + 1 };
-
-        // If window isn't drawn on this scanline, return
-        if (wndw_coords.y > line) || (wndw_coords.x > SCREEN_WIDTH as u8) {
+    fn render_wndw_line(&mut self, pixel_row: &mut [u8], bg_line: &mut [BgPixel], shade_row: &mut [u8], mode: GB) {
+        // The window is only drawn after LY has matched WY this frame, and when WX is 0-166
+        let wx = self.read_io(WX);
+        if !self.wndw_y_triggered || wx > WX_MAX {
             return;
         }
 
@@ -517,42 +677,73 @@ impl PPU {
         let pal_indices = self.get_dmg_bg_indices();
 
         // Get the row of tiles containing our scanline
-        let y = (line - wndw_coords.y) as usize;
-        let row = y % TILESIZE;
+        let y = self.wndw_line as usize;
         let map_y = y / TILESIZE;
-        let start_x = wndw_coords.x as usize;
-        for x in start_x..SCREEN_WIDTH {
+        // Window X position is offset by 7. At WX < 7 the leftmost window pixels are simply offscreen
+        let start_x = wx as isize - WX_OFFSET;
+        for x in 0..SCREEN_WIDTH {
+            let wndw_x = x as isize - start_x;
+            if wndw_x < 0 {
+                continue;
+            }
+            let wndw_x = wndw_x as usize;
+
             // Get coords for current tile
-            let map_x = ((x - start_x) % MAP_PIXELS) / TILESIZE;
+            let map_x = (wndw_x % MAP_PIXELS) / TILESIZE;
             // The index is the cell in question, plus the offset for which map table is being used
             let idx = (map_y * MAP_SIZE + map_x) + (self.get_wndw_tile_map_index() as usize * TILE_MAP_TBL_SIZE);
             let wndw_data = self.tile_maps[idx];
             // The tile indexes in the second tile pattern table ($8800-97ff) are signed
-            let mut tile_index = if self.get_bkgd_wndw_tile_set_index() == 0 {
+            let tile_index = if self.get_bkgd_wndw_tile_set_index() == 0 {
                 (256 + (wndw_data.get_tile_num() as i8 as isize)) as usize
             } else {
                 wndw_data.get_tile_num() as usize
             };
-            tile_index += self.vram_bank * TILE_NUM;
-            let tile = &self.tiles[tile_index];
-            let col = (x - start_x) % TILESIZE;
+
+            let tile = if mode == GB::CGB {
+                let bank_offset = wndw_data.get_vram_bank() * TILE_NUM;
+                &self.tiles[tile_index + bank_offset]
+            } else {
+                &self.tiles[tile_index]
+            };
+
+            let col = wndw_x % TILESIZE;
+            let col = if wndw_data.is_x_flip() {
+                TILESIZE - col - 1
+            } else {
+                col
+            };
+            let row = y % TILESIZE;
+            let row = if wndw_data.is_y_flip() {
+                TILESIZE - row - 1
+            } else {
+                row
+            };
+
             let pixel = tile.get_row(row)[col] as usize;
             let color = if mode == GB::CGB {
-                gbc2rgba(self.cgb_bg_pal_data[2 * pixel], self.cgb_bg_pal_data[2 * pixel + 1])
+                let pal_indices = self.get_cgb_bg_indices(wndw_data.get_pal_num());
+                gbc2rgba(pal_indices[2 * pixel], pal_indices[2 * pixel + 1])
             } else {
+                shade_row[x] = pal_indices[pixel];
                 dmg_pal[pal_indices[pixel] as usize]
             };
+            bg_line[x] = BgPixel { color: pixel as u8, priority: wndw_data.is_bg_priority() };
 
-            for i in 0..COLOR_CHANNELS {
-                pixel_row[COLOR_CHANNELS * x + i] = color[i];
-            }
+            pixel_row[(COLOR_CHANNELS * x)..(COLOR_CHANNELS * (x + 1))].copy_from_slice(&color);
         }
 
-        // The window layer has an odd edge case
-        // If it is disabled mid-frame and then re-enabled, it continues rendering where it was
-        // Thus, we need to keep track of what scanline we finished rendering in case we are disabled
-        // And continue there if re-enabled this frame (and reset this value at start of next)
-        self.last_wndw_line = Some(line);
+        self.wndw_line = self.wndw_line.wrapping_add(1);
+    }
+
+    /// ```
+    /// Reset window
+    ///
+    /// Resets the window's internal line counter and WY trigger, done at the start of every frame
+    /// ```
+    fn reset_wndw(&mut self) {
+        self.wndw_line = 0;
+        self.wndw_y_triggered = false;
     }
 
     /// ```
@@ -560,120 +751,94 @@ impl PPU {
     ///
     /// Renders the given scanline of the sprite layer
     ///
+    /// For each pixel, the highest priority sprite with an opaque pixel there is chosen,
+    /// then it is compared against the background to decide which is shown.
+    /// The background wins if it has a non-zero color and either the sprite is flagged to be
+    /// drawn behind the background, or (CGB only) the BG map attribute has priority set.
+    /// In CGB mode, clearing LCDC bit 0 removes all background priority.
+    ///
     /// Inputs:
     ///     Array to load pixel data into (&[u8])
+    ///     Per-pixel background info (&[BgPixel])
+    ///     Array to load DMG shades into (&[u8])
     ///     Scanline to render (u8)
     ///     GB hardware type
     /// ```
-    fn render_sprite_line(&self, pixel_row: &mut [u8], line: u8, mode: GB) {
-        // Iterate through every sprite
-        let sorted_sprites = self.sort_sprites();
-        let is_8x16 = self.spr_are_8x16();
-        let screen_coords = self.get_scroll_coords();
-        let lcd_control = self.read_io(LCDC);
-        let mut sprites_drawn = 0;
-        for spr in sorted_sprites {
-            if !spr.contains_scanline(line, is_8x16) || !spr.is_onscreen() {
-                continue;
-            }
+    fn render_sprite_line(&self, pixel_row: &mut [u8], bg_line: &[BgPixel], shade_row: &mut [u8], line: u8, mode: GB) {
+        let sprites = self.sort_sprites(line, mode);
+        if sprites.is_empty() {
+            return;
+        }
 
-            sprites_drawn += 1;
-            // System only allows finite number of sprites drawn per line
-            // If we hit threshold, no more sprites can be drawn on this line
+        let is_8x16 = self.spr_are_8x16();
+        let master_priority = mode != GB::CGB || self.read_io(LCDC).get_bit(BG_DISP_BIT);
 
-            // TODO: This has been shown to cause issues on GBC games (See Mario Deluxe)
-            // Need to re-verify whether this is a requirement there as well
-            if sprites_drawn > SPR_PER_LINE && mode != GB::CGB {
-                break;
-            }
+        for (x, bg) in bg_line.iter().enumerate() {
+            for spr in &sprites {
+                let (top_x, top_y) = spr.get_coords();
+                let col = x as i16 - top_x;
+                if col < 0 || col >= TILESIZE as i16 {
+                    continue;
+                }
 
-            let dmg_pal = self.palette.get_spr_pal(spr.get_pal());
-            let pal_indices = self.get_dmg_spr_indices(spr.get_pal());
-            let cgb_colors = self.get_cgb_spr_indices(spr.get_pal());
-            let mut above_bg = spr.is_above_bkgd();
-            let (top_x, top_y) = spr.get_coords();
-            // Get which row in the sprite we're drawing
-            let row = ((line as i16) - top_y) as usize;
-            // If sprite is Y-flipped, adjust row
-            let row = if spr.is_y_flip() {
-                if is_8x16 {
-                    (2 * TILESIZE) - row - 1
+                // Get which row in the sprite we're drawing
+                let row = ((line as i16) - top_y) as usize;
+                // If sprite is Y-flipped, adjust row
+                let row = if spr.is_y_flip() {
+                    if is_8x16 {
+                        (2 * TILESIZE) - row - 1
+                    } else {
+                        TILESIZE - row - 1
+                    }
                 } else {
-                    TILESIZE - row - 1
-                }
-            } else {
-                row
-            };
+                    row
+                };
 
-            let spr_num = if is_8x16 {
-                // In 8x16 mode, lower bit of tile number is ignored
-                // Upper 8x8 tile is NN & $FE
-                // Lower 8x8 tile is NN | $01
-                if row < TILESIZE {
-                    spr.get_tile_num() & 0xFE
+                let spr_num = if is_8x16 {
+                    // In 8x16 mode, lower bit of tile number is ignored
+                    // Upper 8x8 tile is NN & $FE
+                    // Lower 8x8 tile is NN | $01
+                    if row < TILESIZE {
+                        spr.get_tile_num() & 0xFE
+                    } else {
+                        spr.get_tile_num() | 0x01
+                    }
                 } else {
-                    spr.get_tile_num() | 0x01
-                }
-            } else {
-                // If 8x8 sprite, simply get tile num
-                spr.get_tile_num()
-            };
-            let spr_bank = spr_num as usize + (spr.get_vram_bank() * TILE_NUM);
-
-            let tile = &self.tiles[spr_bank];
-            let pixels = tile.get_row(row % TILESIZE);
-            let spr_x = top_x as usize;
-            for col in 0..TILESIZE {
-                let pixel = pixels[col as usize] as usize;
-                let x_offset = if spr.is_x_flip() {
+                    // If 8x8 sprite, simply get tile num
+                    spr.get_tile_num()
+                };
+                let spr_bank = spr_num as usize + (spr.get_vram_bank() * TILE_NUM);
+
+                let col = col as usize;
+                let col = if spr.is_x_flip() {
                     TILESIZE - col - 1
                 } else {
                     col
                 };
+                let pixel = self.tiles[spr_bank].get_row(row % TILESIZE)[col] as usize;
 
-                let pixel_x = spr_x.wrapping_add(x_offset);
-                // Move on if pixel is going to be drawn off-screen
-                if pixel_x >= SCREEN_WIDTH {
+                // Transparent sprite pixels let lower priority sprites show through
+                if pixel == 0 {
                     continue;
                 }
 
-                let pixel_rgba = &pixel_row[(COLOR_CHANNELS * pixel_x)..(COLOR_CHANNELS * (pixel_x + 1))];
-                let bkgd_transparent = if mode == GB::CGB {
-                    // Need to get the specific palette for this background tile
-                    let map_x = (screen_coords.x as usize + pixel_x) % MAP_SIZE;
-                    let map_y = ((screen_coords.y as usize) + (line as usize)) % MAP_SIZE;
-                    // The index is the cell in question, plus the offset for which map table is being used
-                    let idx = (map_y * MAP_SIZE + map_x) + (self.get_bkgd_tile_map_index() as usize * TILE_MAP_TBL_SIZE);
-                    let tile_data = self.tile_maps[idx];
-                    let pal_indices = self.get_cgb_bg_indices(tile_data.get_pal_num());
-                    let bkgd_pal = gbc2rgba(pal_indices[0], pal_indices[1]);
-
-                    // While we have the background tile metadata, see if this tile has priority over sprites
-                    above_bg &= !tile_data.is_bg_priority();
-                    // Master enable, if LCDC.0 cleared, then sprites always display on top
-                    above_bg |= !lcd_control.get_bit(BG_DISP_BIT);
-
-                    // Check if the pixel already drawn is the transparancy color for that BG tile
-                    pixel_rgba == bkgd_pal
-                } else {
-                    pixel_rgba == dmg_pal[0]
-                };
-
-                // Only draw pixel if
-                // - Pixel isn't transparent
-                // - Sprite is going to be drawn above the backgroud OR
-                // - Sprite is below background, but background has transparent color here
-                if pixel != 0 && (above_bg || bkgd_transparent) {
+                let bg_wins = master_priority && bg.color != 0 && (bg.priority || !spr.is_above_bkgd());
+                if !bg_wins {
                     let color = if mode == GB::CGB {
+                        let cgb_colors = self.get_cgb_spr_indices(spr.get_pal());
                         gbc2rgba(cgb_colors[2 * pixel], cgb_colors[2 * pixel + 1])
                     } else {
+                        let dmg_pal = self.palette.get_spr_pal(spr.get_pal());
+                        let pal_indices = self.get_dmg_spr_indices(spr.get_pal());
+                        shade_row[x] = pal_indices[pixel];
                         dmg_pal[pal_indices[pixel] as usize]
                     };
 
-                    for i in 0..COLOR_CHANNELS {
-                        pixel_row[COLOR_CHANNELS * pixel_x + i] = color[i];
-                    }
+                    pixel_row[(COLOR_CHANNELS * x)..(COLOR_CHANNELS * (x + 1))].copy_from_slice(&color);
                 }
+
+                // Only the highest priority opaque sprite pixel is considered, even if the background wins
+                break;
             }
         }
     }
@@ -775,19 +940,32 @@ impl PPU {
     /// ```
     /// Sort sprites
     ///
-    /// Sort sprites into correct drawing order
+    /// Selects the sprites on this scanline, sorted into priority order (highest first)
+    ///
+    /// The hardware scans OAM in order, picking the first 10 sprites which overlap the line.
+    /// On DMG, the sprite with the lowest X-coordinate has priority, with ties going to the
+    /// lowest OAM index. On CGB, priority is simply by OAM index.
+    ///
+    /// Inputs:
+    ///     Scanline being rendered (u8)
+    ///     GB hardware type
     ///
     /// Output:
     ///     Sorted sprites (Vec<Sprite>)
     /// ```
-    fn sort_sprites(&self) -> Vec<Sprite> {
-        // In event of overlap, sprites are drawn
-        // (on DMG) with the lowest x-coordinate on top.
-        // If tie, lowest sprite number goes on top
-        let mut sprites = self.oam.to_vec();
-        // Reverse the vector so that lower sprite number is earlier in a tie
-        sprites.reverse();
-        sprites.sort_by(|a, b| b.get_coords().0.cmp(&a.get_coords().0));
+    fn sort_sprites(&self, line: u8, mode: GB) -> Vec<Sprite> {
+        let is_8x16 = self.spr_are_8x16();
+        let mut sprites: Vec<Sprite> = self.oam.iter()
+            .filter(|spr| spr.contains_scanline(line, is_8x16))
+            .take(SPR_PER_LINE)
+            .copied()
+            .collect();
+
+        if mode != GB::CGB {
+            // Stable sort, so ties remain in OAM order
+            sprites.sort_by_key(|spr| spr.get_coords().0);
+        }
+
         sprites
     }
 
@@ -804,6 +982,39 @@ impl PPU {
         lcd_control.get_bit(LCD_DISP_BIT)
     }
 
+    /// ```
+    /// Is access blocked
+    ///
+    /// Whether the CPU is currently locked out of the given address by the LCD
+    /// OAM is inaccessible during modes 2 and 3, VRAM and CGB palette data during mode 3
+    ///
+    /// Inputs:
+    ///     Address being accessed (u16)
+    ///     System mode (GB)
+    ///
+    /// Output:
+    ///     Whether the access should be ignored (bool)
+    /// ```
+    fn is_access_blocked(&self, addr: u16, mode: GB) -> bool {
+        if !self.strict_vram_access || !self.is_lcd_dspl() {
+            return false;
+        }
+
+        let lcd_mode = self.lcd_mode.get_mode();
+        match addr {
+            OAM_START..=OAM_END => {
+                lcd_mode == LcdModeType::OAMReadMode || lcd_mode == LcdModeType::VRAMReadMode
+            },
+            TILE_SET..=TILE_MAP_END => {
+                lcd_mode == LcdModeType::VRAMReadMode
+            },
+            BGPD | OBPD => {
+                mode == GB::CGB && lcd_mode == LcdModeType::VRAMReadMode
+            },
+            _ => false
+        }
+    }
+
     /// ```
     /// Is background displayed
     ///
@@ -826,12 +1037,16 @@ impl PPU {
     ///
     /// Is the window layer currently visible
     ///
+    /// Input:
+    ///     Hardware type (GB)
+    ///
     /// Output:
     ///     Whether window layer is visible (bool)
     /// ```
-    fn is_wndw_dspl(&self) -> bool {
+    fn is_wndw_dspl(&self, mode: GB) -> bool {
+        // On DMG, clearing LCDC bit 0 blanks the window as well as the background
         let lcd_control = self.read_io(LCDC);
-        lcd_control.get_bit(WNDW_DISP_BIT)
+        lcd_control.get_bit(WNDW_DISP_BIT) && self.is_bkgd_dspl(mode)
     }
 
     /// ```
@@ -925,21 +1140,6 @@ impl PPU {
         Point { x: scroll_x, y: scroll_y }
     }
 
-    /// ```
-    /// Get window coords
-    ///
-    /// Returns the window position from the WX and WY registers
-    ///
-    /// Output:
-    ///     Location of the window (Point)
-    /// ```
-    fn get_wndw_coords(&self) -> Point<u8> {
-        let wndw_x = self.read_io(WX).saturating_sub(7);
-        let wndw_y = self.read_io(WY);
-
-        Point{ x: wndw_x, y: wndw_y }
-    }
-
     /// ```
     /// Read CGB Background color data
     ///
@@ -1001,3 +1201,260 @@ impl PPU {
         }
     }
 }
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    const TILE_BYTE: u8 = 0x42;
+
+    // Turns the LCD on with a known tile byte in VRAM, then runs it until the given mode
+    fn ppu_in_mode(strict: bool, lcd_mode: LcdModeType) -> PPU {
+        let mut ppu = PPU::new();
+        ppu.set_strict_vram_access(strict);
+        ppu.write_vram(TILE_SET, TILE_BYTE, GB::DMG);
+        ppu.write_vram(LCDC, 0x91, GB::DMG);
+        while ppu.get_lcd_mode() != lcd_mode {
+            ppu.update(1);
+        }
+        ppu
+    }
+
+    // Fills every row of a tile with the same pair of bytes
+    fn write_tile(ppu: &mut PPU, tile: u16, low: u8, high: u8, mode: GB) {
+        for row in 0..(TILESIZE as u16) {
+            ppu.write_vram(TILE_SET + tile * TILE_BYTES + 2 * row, low, mode);
+            ppu.write_vram(TILE_SET + tile * TILE_BYTES + 2 * row + 1, high, mode);
+        }
+    }
+
+    // Runs the LCD, rendering each scanline as the bus would, until VBLANK begins
+    fn run_frame(ppu: &mut PPU, mode: GB) {
+        loop {
+            match ppu.update(4).lcd_result {
+                LcdResults::RenderScanline => { ppu.render_scanline(mode) },
+                LcdResults::RenderFrame => { return },
+                _ => {}
+            }
+        }
+    }
+
+    // Runs the LCD until the next scanline is rendered, returning which line it was
+    fn render_next_line(ppu: &mut PPU, mode: GB) -> u8 {
+        while ppu.update(4).lcd_result != LcdResults::RenderScanline {}
+        ppu.render_scanline(mode);
+        ppu.read_vram(LY, None, mode)
+    }
+
+    // Places an 8x8 sprite on the top line of the screen, at the given OAM X coordinate
+    fn write_sprite(ppu: &mut PPU, index: u16, x: u8, tile: u8, mode: GB) {
+        let addr = OAM_START + index * OAM_BYTE_SIZE;
+        ppu.write_vram(addr, 16, mode);
+        ppu.write_vram(addr + 1, x, mode);
+        ppu.write_vram(addr + 2, tile, mode);
+        ppu.write_vram(addr + 3, 0x00, mode);
+    }
+
+    // Color of a pixel on screen, for CGB where there are no DMG shades
+    fn pixel_color(ppu: &PPU, line: u8, x: usize) -> [u8; COLOR_CHANNELS] {
+        let start = COLOR_CHANNELS * (line as usize * SCREEN_WIDTH + x);
+        let mut color = [0; COLOR_CHANNELS];
+        color.copy_from_slice(&ppu.screen_buffer[start..(start + COLOR_CHANNELS)]);
+        color
+    }
+
+    // Counts the STAT interrupts requested until the given line begins
+    fn stat_irqs_until_line(ppu: &mut PPU, line: u8) -> usize {
+        let mut irqs = 0;
+        while ppu.read_vram(LY, None, GB::DMG) != line {
+            irqs += ppu.update(4).interrupt as usize;
+        }
+        irqs
+    }
+
+    #[test]
+    fn strict_vram_blocked_in_mode_3() {
+        let mut ppu = ppu_in_mode(true, LcdModeType::VRAMReadMode);
+        assert_eq!(ppu.read_vram(TILE_SET, None, GB::DMG), 0xFF);
+        // Debugger reads pass a bank, and still see the real value
+        assert_eq!(ppu.read_vram(TILE_SET, Some(0), GB::DMG), TILE_BYTE);
+
+        ppu.write_vram(TILE_SET, 0x00, GB::DMG);
+        assert_eq!(ppu.read_vram(TILE_SET, Some(0), GB::DMG), TILE_BYTE);
+    }
+
+    #[test]
+    fn strict_vram_allowed_outside_mode_3() {
+        let ppu = ppu_in_mode(true, LcdModeType::HBLANK);
+        assert_eq!(ppu.read_vram(TILE_SET, None, GB::DMG), TILE_BYTE);
+    }
+
+    #[test]
+    fn vram_unrestricted_when_not_strict() {
+        let ppu = ppu_in_mode(false, LcdModeType::VRAMReadMode);
+        assert_eq!(ppu.read_vram(TILE_SET, None, GB::DMG), TILE_BYTE);
+    }
+
+    #[test]
+    fn lcd_off_holds_ly_0_and_mode_0() {
+        let mut ppu = ppu_in_mode(false, LcdModeType::VBLANK);
+        assert_ne!(ppu.read_vram(LY, None, GB::DMG), 0);
+
+        ppu.write_vram(LCDC, 0x11, GB::DMG);
+        for _ in 0..mode::FRAME_LEN {
+            assert!(!ppu.update(1).interrupt);
+        }
+        assert_eq!(ppu.read_vram(LY, None, GB::DMG), 0);
+        assert_eq!(ppu.read_vram(STAT, None, GB::DMG) & 0b11, 0);
+    }
+
+    #[test]
+    fn first_frame_after_enable_is_blank() {
+        let mut ppu = ppu_in_mode(false, LcdModeType::HBLANK);
+        write_tile(&mut ppu, 0, 0xFF, 0xFF, GB::DMG);
+        ppu.write_vram(BGP, 0xE4, GB::DMG);
+
+        ppu.write_vram(LCDC, 0x11, GB::DMG);
+        ppu.write_vram(LCDC, 0x91, GB::DMG);
+        run_frame(&mut ppu, GB::DMG);
+        assert!(ppu.render_screen().iter().all(|&byte| byte == 0xFF));
+
+        // The screen comes back once the LCD has been through VBLANK
+        run_frame(&mut ppu, GB::DMG);
+        assert!(ppu.render_screen().iter().any(|&byte| byte != 0xFF));
+    }
+
+    #[test]
+    fn stat_irq_blocked_while_line_high() {
+        let mut ppu = ppu_in_mode(false, LcdModeType::VRAMReadMode);
+        // HBLANK and OAM sources, so the line stays high from each HBLANK into the next line's OAM scan
+        // Only the HBLANKs of lines 1-10 raise an interrupt
+        ppu.write_vram(STAT, 0x28, GB::DMG);
+        assert_eq!(stat_irqs_until_line(&mut ppu, 11), 10);
+    }
+
+    #[test]
+    fn stat_irq_blocked_during_lyc_match() {
+        let mut ppu = ppu_in_mode(false, LcdModeType::VRAMReadMode);
+        // HBLANK and LY=LYC sources. Line 4's HBLANK holds the line high into the LY=LYC match on line 5,
+        // which holds it through line 5's HBLANK, so only the HBLANKs of lines 1-4, 6 and 7 raise an interrupt
+        ppu.write_vram(LYC, 5, GB::DMG);
+        ppu.write_vram(STAT, 0x48, GB::DMG);
+        assert_eq!(stat_irqs_until_line(&mut ppu, 8), 6);
+    }
+
+    #[test]
+    fn line_143_rendered_before_vblank() {
+        let mut ppu = ppu_in_mode(false, LcdModeType::HBLANK);
+        write_tile(&mut ppu, 0, 0xFF, 0xFF, GB::DMG);
+        ppu.write_vram(BGP, 0xE4, GB::DMG);
+
+        run_frame(&mut ppu, GB::DMG);
+        assert_eq!(ppu.read_vram(LY, None, GB::DMG), 144);
+        let last_line = (SCREEN_HEIGHT - 1) * SCREEN_WIDTH;
+        assert!(ppu.shade_buffer[last_line..].iter().all(|&shade| shade == 3));
+    }
+
+    #[test]
+    fn window_line_resumes_after_being_hidden() {
+        const WNDW_ON: u8 = 0xF1; // Window uses the $9C00 map, background the empty $9800 map
+        const WNDW_OFF: u8 = 0xD1;
+
+        let mut ppu = PPU::new();
+        // Only the first row of the window's tiles is dark, so each dark line is a multiple of 8 window lines in
+        ppu.write_vram(TILE_SET + TILE_BYTES, 0xFF, GB::DMG);
+        ppu.write_vram(TILE_SET + TILE_BYTES + 1, 0xFF, GB::DMG);
+        for addr in (TILE_MAP + TILE_MAP_TBL_SIZE as u16)..=TILE_MAP_END {
+            ppu.write_vram(addr, 1, GB::DMG);
+        }
+        ppu.write_vram(BGP, 0xE4, GB::DMG);
+        ppu.write_vram(WY, 0, GB::DMG);
+        // Restart the LCD, so the frame begins at line 0
+        ppu.write_vram(LCDC, WNDW_OFF & 0x7F, GB::DMG);
+        ppu.write_vram(LCDC, WNDW_ON, GB::DMG);
+
+        let mut shades = Vec::new();
+        for line in 0..=16 {
+            // Hidden by LCDC.5 on lines 4-5, and moved offscreen by WX on lines 6-8
+            let (lcdc, wx) = match line {
+                4..=5 => { (WNDW_OFF, 7) },
+                6..=8 => { (WNDW_ON, WX_MAX + 1) },
+                _ =>     { (WNDW_ON, 7) },
+            };
+            ppu.write_vram(LCDC, lcdc, GB::DMG);
+            ppu.write_vram(WX, wx, GB::DMG);
+            assert_eq!(render_next_line(&mut ppu, GB::DMG), line);
+            shades.push(ppu.shade_buffer[line as usize * SCREEN_WIDTH]);
+        }
+
+        // Window lines 0-3 are drawn on lines 0-3, then line 9 picks up from window line 4
+        assert_eq!(shades[0], 3);
+        assert_eq!(shades[9..=12], [0, 0, 0, 0]);
+        assert_eq!(shades[13], 3);
+        assert_eq!(shades[16], 0);
+    }
+
+    // Two overlapping sprites on the top line. Only the first in OAM covers x=8, and only the second covers x=0
+    fn overlapping_sprites(mode: GB) -> PPU {
+        let mut ppu = PPU::new();
+        write_tile(&mut ppu, 1, 0xFF, 0x00, mode);
+        write_tile(&mut ppu, 2, 0x00, 0xFF, mode);
+        write_sprite(&mut ppu, 0, 12, 1, mode);
+        write_sprite(&mut ppu, 1, 8, 2, mode);
+        ppu.write_vram(OBP0, 0xE4, mode);
+        // Sprite palette 0, colors 1 and 2 are red and green
+        ppu.write_vram(OBPI, 0x82, mode);
+        for byte in [0x1F, 0x00, 0xE0, 0x03] {
+            ppu.write_vram(OBPD, byte, mode);
+        }
+
+        ppu.write_vram(LCDC, 0x13, mode);
+        ppu.write_vram(LCDC, 0x93, mode);
+        assert_eq!(render_next_line(&mut ppu, mode), 0);
+        ppu
+    }
+
+    #[test]
+    fn dmg_sprite_priority_by_x() {
+        let ppu = overlapping_sprites(GB::DMG);
+        // The second sprite is further left, so wins where they overlap
+        assert_eq!(ppu.shade_buffer[4], 2);
+        assert_eq!(ppu.shade_buffer[8], 1);
+    }
+
+    #[test]
+    fn cgb_sprite_priority_by_oam_index() {
+        let ppu = overlapping_sprites(GB::CGB);
+        let red = gbc2rgba(0x1F, 0x00);
+        let green = gbc2rgba(0xE0, 0x03);
+        assert_eq!(pixel_color(&ppu, 0, 0), green);
+        assert_eq!(pixel_color(&ppu, 0, 4), red);
+        assert_eq!(pixel_color(&ppu, 0, 8), red);
+    }
+
+    #[test]
+    fn lcdc_0_overrides_cgb_bg_priority() {
+        let mut ppu = PPU::new();
+        write_tile(&mut ppu, 0, 0xFF, 0xFF, GB::CGB);
+        write_tile(&mut ppu, 1, 0xFF, 0x00, GB::CGB);
+        write_sprite(&mut ppu, 0, 8, 1, GB::CGB);
+        // The background tile under the sprite has priority set in its attributes
+        ppu.write_vram(VBK, 1, GB::CGB);
+        ppu.write_vram(TILE_MAP, 0x80, GB::CGB);
+        ppu.write_vram(VBK, 0, GB::CGB);
+        ppu.write_vram(OBPI, 0x82, GB::CGB);
+        ppu.write_vram(OBPD, 0x1F, GB::CGB);
+        ppu.write_vram(OBPD, 0x00, GB::CGB);
+        let red = gbc2rgba(0x1F, 0x00);
+
+        ppu.write_vram(LCDC, 0x13, GB::CGB);
+        ppu.write_vram(LCDC, 0x93, GB::CGB);
+        assert_eq!(render_next_line(&mut ppu, GB::CGB), 0);
+        assert_ne!(pixel_color(&ppu, 0, 0), red);
+
+        // With LCDC.0 clear, sprites are drawn over the background regardless of priority
+        ppu.write_vram(LCDC, 0x92, GB::CGB);
+        assert_eq!(render_next_line(&mut ppu, GB::CGB), 1);
+        assert_eq!(pixel_color(&ppu, 1, 0), red);
+    }
+}
@@ -3,15 +3,17 @@ const HBLANK_LEN: usize = 204;
 const VBLANK_LEN: usize = 456;
 const OAM_READ_LEN: usize = 80;
 const VRAM_READ_LEN: usize = 172;
+pub const FRAME_LEN: usize = 70224;
 
-const VBLANK_LINE_START: u8 = 143;
-const VBLANK_LINE_END: u8 = VBLANK_LINE_START + 10;
+const VBLANK_LINE_START: u8 = 144;
+const VBLANK_LINE_END: u8 = VBLANK_LINE_START + 9;
 
 #[derive(PartialEq)]
 pub enum LcdResults {
     NoAction,
     RenderScanline,
-    RenderFrame
+    RenderFrame,
+    BlankFrame
 }
 
 #[derive(PartialEq, Clone, Copy)]
@@ -36,7 +38,8 @@ impl LcdModeType {
 pub struct Lcd {
     cycles: usize,
     line: u8,
-    mode: LcdModeType
+    mode: LcdModeType,
+    enabled: bool,
 }
 
 impl Default for Lcd {
@@ -50,7 +53,8 @@ impl Lcd {
         Lcd {
             cycles: 0,
             line: 0,
-            mode: LcdModeType::HBLANK
+            mode: LcdModeType::HBLANK,
+            enabled: true,
         }
     }
 
@@ -60,15 +64,25 @@ impl Lcd {
     /// Adds specified number of cycles to the LCD counter, updating scanline and blank timings
     ///
     /// Input:
-    ///     Number of cycles of most recent instruction (u8)
+    ///     Number of dots which have elapsed (u8)
     ///
     /// Output:
     ///     Action to take following this lcd cycle (LcdResults)
     /// ```
-    pub fn lcd_step(&mut self, cycles: u8) -> LcdResults {
-        self.cycles += cycles as usize;
+    pub fn lcd_step(&mut self, dots: u8) -> LcdResults {
+        self.cycles += dots as usize;
         let mut result = LcdResults::NoAction;
 
+        // While the LCD is off, the scanline and mode are frozen
+        // Still report when a frame's worth of time has passed, so the frontend keeps running
+        if !self.enabled {
+            if self.cycles >= FRAME_LEN {
+                self.cycles -= FRAME_LEN;
+                result = LcdResults::BlankFrame;
+            }
+            return result;
+        }
+
         match self.mode {
             // Screen gets drawn after final hblank
             LcdModeType::HBLANK => {
@@ -151,6 +165,57 @@ impl Lcd {
         self.line = 0;
     }
 
+    /// ```
+    /// Skip to VBLANK line
+    ///
+    /// Moves the LCD to the start of the given VBLANK scanline
+    /// Used when a boot ROM hands over to the game partway through a frame
+    ///
+    /// Input:
+    ///     Scanline to move to (u8)
+    /// ```
+    pub fn skip_to_vblank_line(&mut self, line: u8) {
+        self.cycles = 0;
+        self.line = line;
+        self.mode = LcdModeType::VBLANK;
+    }
+
+    /// ```
+    /// Disable
+    ///
+    /// Turns the LCD off, which resets LY to 0 and holds the LCD in mode 0
+    /// ```
+    pub fn disable(&mut self) {
+        self.enabled = false;
+        self.cycles = 0;
+        self.line = 0;
+        self.mode = LcdModeType::HBLANK;
+    }
+
+    /// ```
+    /// Enable
+    ///
+    /// Turns the LCD back on, restarting timing from the top of line 0
+    /// ```
+    pub fn enable(&mut self) {
+        self.enabled = true;
+        self.cycles = 0;
+        self.line = 0;
+        self.mode = LcdModeType::OAMReadMode;
+    }
+
+    /// ```
+    /// Is enabled
+    ///
+    /// Returns whether the LCD is currently on
+    ///
+    /// Output:
+    ///     Whether the LCD is on (bool)
+    /// ```
+    pub fn is_enabled(&self) -> bool {
+        self.enabled
+    }
+
     /// ```
     /// Is VBLANK iterrupt
     ///
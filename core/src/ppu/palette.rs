@@ -1,3 +1,5 @@
+use super::compat::{get_compat_palette, CompatPalette};
+use crate::io::Buttons;
 use crate::utils::{COLOR_CHANNELS, merge_bytes};
 
 pub const DMG_PAL_SIZE: usize = 4;
@@ -20,8 +22,41 @@ pub enum Palettes {
     INVERTED
 }
 
+impl Palettes {
+    /// ```
+    /// From keypad
+    ///
+    /// Gets the palette the CGB boot ROM uses for DMG games when a button combination is held
+    ///
+    /// Inputs:
+    ///     Held direction (Buttons)
+    ///     Held A or B button, if any (Option<Buttons>)
+    ///
+    /// Output:
+    ///     Palette for the combination, if any (Option<Palettes>)
+    /// ```
+    pub fn from_keypad(dir: Buttons, btn: Option<Buttons>) -> Option<Palettes> {
+        match (dir, btn) {
+            (Buttons::Up, None) =>                  { Some(Palettes::BROWN) },
+            (Buttons::Up, Some(Buttons::A)) =>      { Some(Palettes::RED) },
+            (Buttons::Up, Some(Buttons::B)) =>      { Some(Palettes::DARK_BROWN) },
+            (Buttons::Left, None) =>                { Some(Palettes::BLUE) },
+            (Buttons::Left, Some(Buttons::A)) =>    { Some(Palettes::DARK_BLUE) },
+            (Buttons::Left, Some(Buttons::B)) =>    { Some(Palettes::GRAYSCALE) },
+            (Buttons::Down, None) =>                { Some(Palettes::PASTEL) },
+            (Buttons::Down, Some(Buttons::A)) =>    { Some(Palettes::ORANGE) },
+            (Buttons::Down, Some(Buttons::B)) =>    { Some(Palettes::YELLOW) },
+            (Buttons::Right, None) =>               { Some(Palettes::GREEN) },
+            (Buttons::Right, Some(Buttons::A)) =>   { Some(Palettes::DARK_GREEN) },
+            (Buttons::Right, Some(Buttons::B)) =>   { Some(Palettes::INVERTED) },
+            _ =>                                    { None }
+        }
+    }
+}
+
 pub struct Palette {
     sys_pal: Palettes,
+    compat_pal: Option<CompatPalette>,
 }
 
 impl Default for Palette {
@@ -34,6 +69,7 @@ impl Palette {
     pub fn new() -> Palette {
         Palette {
             sys_pal: Palettes::GRAYSCALE,
+            compat_pal: None,
         }
     }
 
@@ -47,6 +83,20 @@ impl Palette {
     /// ```
     pub fn set_sys_pal(&mut self, pal: Palettes) {
         self.sys_pal = pal;
+        self.compat_pal = None;
+    }
+
+    /// ```
+    /// Set compatibility palette
+    ///
+    /// Use the palette the CGB boot ROM would choose for the game, instead of the system palette
+    ///
+    /// Inputs:
+    ///     Title checksum, or None if not a Nintendo game (Option<u8>)
+    ///     Fourth letter of the title (u8)
+    /// ```
+    pub fn set_compat_pal(&mut self, checksum: Option<u8>, fourth_letter: u8) {
+        self.compat_pal = Some(get_compat_palette(checksum, fourth_letter));
     }
 
     /// ```
@@ -58,6 +108,10 @@ impl Palette {
     ///     RGBA values for palette ([4x[RGBA u8 values]])
     /// ```
     pub fn get_bg_pal(&self) -> [[u8; COLOR_CHANNELS]; DMG_PAL_SIZE] {
+        if let Some(compat) = &self.compat_pal {
+            return compat.bg;
+        }
+
         match self.sys_pal {
             Palettes::GRAYSCALE => {
                 [[255, 255, 255, 255],
@@ -162,6 +216,10 @@ impl Palette {
     ///     RGBA values for palette ([4x[RGBA u8 values]])
     /// ```
     fn get_obj0_pal(&self) -> [[u8; COLOR_CHANNELS]; DMG_PAL_SIZE] {
+        if let Some(compat) = &self.compat_pal {
+            return compat.obj0;
+        }
+
         match self.sys_pal {
             Palettes::GRAYSCALE => { self.get_bg_pal() },
             Palettes::BROWN =>     { self.get_bg_pal() },
@@ -217,6 +275,10 @@ impl Palette {
     ///     RGBA values for palette ([4x[RGBA u8 values]])
     /// ```
     fn get_obj1_pal(&self) -> [[u8; COLOR_CHANNELS]; DMG_PAL_SIZE] {
+        if let Some(compat) = &self.compat_pal {
+            return compat.obj1;
+        }
+
         match self.sys_pal {
             Palettes::GRAYSCALE =>  { self.get_bg_pal() },
             Palettes::BROWN =>      { self.get_bg_pal() },
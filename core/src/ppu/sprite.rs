@@ -22,8 +22,6 @@ use crate::utils::{GB, ModifyBits, TILESIZE};
 
 const X_OFFSET: i16 = 8;
 const Y_OFFSET: i16 = 16;
-const X_OFFSCREEN: i16 = 168;
-const Y_OFFSCREEN: i16 = 160;
 
 const Y_POS_BYTE: u16 = 0;
 const X_POS_BYTE: u16 = 1;
@@ -105,21 +103,6 @@ impl Sprite {
         self.data[index as usize]
     }
 
-    /// ```
-    /// Is onscreen
-    ///
-    /// Is sprite onscreen?
-    ///
-    /// Output:
-    ///     Is sprite onscreen (bool)
-    /// ```
-    pub fn is_onscreen(&self) -> bool {
-        let x_visible = self.x > -X_OFFSET && self.x < X_OFFSCREEN;
-        let y_visible = self.y > -Y_OFFSET && self.y < Y_OFFSCREEN;
-
-        x_visible || y_visible
-    }
-
     /// ```
     /// Contains scanline?
     ///
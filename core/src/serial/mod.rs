@@ -0,0 +1,416 @@
+// Serial port functions
+
+/*
+ * Game Boy serial port
+ * Address $FF01 - SB, Serial transfer data
+ * Address $FF02 - SC, Serial transfer control
+ *
+ * SC bit | Function
+ * -------+---------
+ * 7      | Transfer start/in progress
+ * 6-2    | Unused
+ * 1      | Clock speed (CGB only), high for fast
+ * 0      | Clock source, high if internal
+ *
+ * A transfer shifts SB out one bit at a time, MSB first, while the other
+ * side's bits are shifted in. Whichever side uses the internal clock drives
+ * the transfer, the other side waits for it. Once 8 bits have been shifted,
+ * SC bit 7 is cleared and the serial interrupt is requested.
+ */
+
+pub mod printer;
+pub mod tcp;
+
+use crate::utils::{GB, ModifyBits};
+
+use std::io;
+
+pub const SB: u16 = 0xFF01;
+pub const SC: u16 = 0xFF02;
+
+const SC_START_BIT: u8 = 7;
+const SC_SPEED_BIT: u8 = 1;
+const SC_CLOCK_BIT: u8 = 0;
+
+// Machine cycles per bit using the internal clock. The serial clock is derived from the
+// system clock, so this holds in both normal and double speed mode
+const NORMAL_BIT_CYCLES: u16 = 128; // 8192 Hz
+const FAST_BIT_CYCLES: u16 = 4;     // 262144 Hz
+
+/// ```
+/// Serial link
+///
+/// Whatever is on the other end of the link cable
+/// ```
+pub trait SerialLink {
+    /// ```
+    /// Exchange
+    ///
+    /// Called when this Game Boy, driving the clock, finishes shifting out a byte
+    /// If the other side's byte isn't known yet, the transfer stays in progress until poll_reply returns it
+    ///
+    /// Input:
+    ///     Byte sent to the other side (u8)
+    ///
+    /// Output:
+    ///     Byte received from the other side, if already known (Option<u8>)
+    /// ```
+    fn exchange(&mut self, out: u8) -> Option<u8>;
+
+    /// ```
+    /// Poll reply
+    ///
+    /// Called every machine cycle after exchange has returned None, until the other side's byte arrives
+    ///
+    /// Input:
+    ///     Machine cycles since exchange or the last poll (u8)
+    ///
+    /// Output:
+    ///     Byte received from the other side, if it has arrived (Option<u8>)
+    /// ```
+    fn poll_reply(&mut self, _m_cycles: u8) -> Option<u8> {
+        Some(0xFF)
+    }
+
+    /// ```
+    /// Poll external
+    ///
+    /// Called every machine cycle while this Game Boy is waiting on an external clock
+    /// If the other side has clocked a full byte in, it is returned, and the outgoing byte is taken
+    ///
+    /// Input:
+    ///     Byte waiting to be sent to the other side (u8)
+    ///
+    /// Output:
+    ///     Byte received from the other side, if a transfer occurred (Option<u8>)
+    /// ```
+    fn poll_external(&mut self, _out: u8) -> Option<u8> {
+        None
+    }
+
+    /// ```
+    /// Take error
+    ///
+    /// Returns the latest error the device ran into, if any, clearing it
+    /// Devices keep running after an error, so the frontend decides how to report it
+    ///
+    /// Output:
+    ///     Error since last called (Option<io::Error>)
+    /// ```
+    fn take_error(&mut self) -> Option<io::Error> {
+        None
+    }
+}
+
+/// ```
+/// Disconnected link
+///
+/// No cable is attached. Sent bytes are lost, and every bit read is high
+/// ```
+pub struct DisconnectedLink;
+
+impl SerialLink for DisconnectedLink {
+    fn exchange(&mut self, _out: u8) -> Option<u8> {
+        Some(0xFF)
+    }
+}
+
+pub struct Serial {
+    sb: u8,
+    sc: u8,
+    out: u8,
+    bits_remaining: u8,
+    bit_cycles: u16,
+    awaiting_reply: bool,
+    link: Box<dyn SerialLink>,
+    capture: Option<Vec<u8>>,
+}
+
+impl Default for Serial {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+impl Serial {
+    pub fn new() -> Serial {
+        Serial {
+            sb: 0,
+            sc: 0,
+            out: 0,
+            bits_remaining: 0,
+            bit_cycles: 0,
+            awaiting_reply: false,
+            link: Box::new(DisconnectedLink),
+            capture: None,
+        }
+    }
+
+    /// ```
+    /// Set link
+    ///
+    /// Attaches a new device to the other end of the link cable
+    ///
+    /// Input:
+    ///     Link cable backend (Box<dyn SerialLink>)
+    /// ```
+    pub fn set_link(&mut self, link: Box<dyn SerialLink>) {
+        self.link = link;
+    }
+
+    /// ```
+    /// Take link error
+    ///
+    /// Returns the latest error from the device on the other end of the link cable, if any
+    ///
+    /// Output:
+    ///     Error since last called (Option<io::Error>)
+    /// ```
+    pub fn take_link_error(&mut self) -> Option<io::Error> {
+        self.link.take_error()
+    }
+
+    /// ```
+    /// Set capture
+    ///
+    /// Toggles recording of every byte sent using the internal clock
+    /// Test ROMs commonly print their results this way
+    ///
+    /// Input:
+    ///     Whether to record sent bytes (bool)
+    /// ```
+    pub fn set_capture(&mut self, enabled: bool) {
+        self.capture = if enabled { Some(Vec::new()) } else { None };
+    }
+
+    /// ```
+    /// Get capture
+    ///
+    /// Returns all bytes sent since capturing was enabled
+    ///
+    /// Output:
+    ///     Sent bytes, if capturing (Option<&[u8]>)
+    /// ```
+    pub fn get_capture(&self) -> Option<&[u8]> {
+        self.capture.as_deref()
+    }
+
+    /// ```
+    /// Read serial
+    ///
+    /// Reads from the serial registers
+    ///
+    /// Inputs:
+    ///     Address of register (u16)
+    ///     GB hardware type (GB)
+    ///
+    /// Output:
+    ///     Value of register (u8)
+    /// ```
+    pub fn read_serial(&self, addr: u16, mode: GB) -> u8 {
+        match addr {
+            SB => { self.sb },
+            SC => {
+                // Unused bits read high, as does the speed bit on DMG
+                if mode == GB::CGB {
+                    self.sc | 0b0111_1100
+                } else {
+                    self.sc | 0b0111_1110
+                }
+            },
+            _ => { panic!("Trying to read a non-serial register") }
+        }
+    }
+
+    /// ```
+    /// Write serial
+    ///
+    /// Writes to the serial registers, starting a transfer if requested
+    ///
+    /// Inputs:
+    ///     Address of register (u16)
+    ///     Value to write (u8)
+    ///     GB hardware type (GB)
+    /// ```
+    pub fn write_serial(&mut self, addr: u16, val: u8, mode: GB) {
+        match addr {
+            SB => { self.sb = val },
+            SC => {
+                self.sc = if mode == GB::CGB { val & 0b1000_0011 } else { val & 0b1000_0001 };
+                self.bits_remaining = if self.sc.get_bit(SC_START_BIT) { 8 } else { 0 };
+                self.bit_cycles = 0;
+                self.awaiting_reply = false;
+                self.out = self.sb;
+
+                if self.sc.get_bit(SC_START_BIT) && self.sc.get_bit(SC_CLOCK_BIT) {
+                    if let Some(capture) = &mut self.capture {
+                        capture.push(self.sb);
+                    }
+                }
+            },
+            _ => { panic!("Trying to write to non-serial register") }
+        }
+    }
+
+    /// ```
+    /// Tick
+    ///
+    /// Advances any transfer in progress
+    ///
+    /// Input:
+    ///     Number of CPU machine cycles which have elapsed (u8)
+    ///
+    /// Output:
+    ///     Whether a transfer completed, requesting an interrupt (bool)
+    /// ```
+    pub fn tick(&mut self, m_cycles: u8) -> bool {
+        if self.awaiting_reply {
+            return match self.link.poll_reply(m_cycles) {
+                Some(incoming) => {
+                    self.sb = incoming;
+                    self.finish_transfer();
+                    true
+                },
+                None => { false }
+            };
+        }
+
+        if self.bits_remaining == 0 {
+            return false;
+        }
+
+        if !self.sc.get_bit(SC_CLOCK_BIT) {
+            // Externally clocked, the other side decides when the transfer occurs
+            return match self.link.poll_external(self.sb) {
+                Some(incoming) => {
+                    self.sb = incoming;
+                    self.finish_transfer();
+                    true
+                },
+                None => { false }
+            };
+        }
+
+        let period = if self.sc.get_bit(SC_SPEED_BIT) { FAST_BIT_CYCLES } else { NORMAL_BIT_CYCLES };
+        self.bit_cycles += m_cycles as u16;
+        while self.bit_cycles >= period && self.bits_remaining > 0 {
+            self.bit_cycles -= period;
+            self.bits_remaining -= 1;
+
+            if self.bits_remaining == 0 {
+                // The transfer only finishes once the other side's byte is known
+                match self.link.exchange(self.out) {
+                    Some(incoming) => {
+                        self.sb = incoming;
+                        self.finish_transfer();
+                        return true;
+                    },
+                    None => {
+                        self.awaiting_reply = true;
+                        return false;
+                    }
+                }
+            }
+
+            // Until the other side's byte is known, bits shift in high, as with no cable attached
+            self.sb = (self.sb << 1) | 1;
+        }
+
+        false
+    }
+}
+
+// Private functions
+impl Serial {
+    /// ```
+    /// Finish transfer
+    ///
+    /// Marks the current transfer as complete
+    /// ```
+    fn finish_transfer(&mut self) {
+        self.sc.clear_bit(SC_START_BIT);
+        self.bits_remaining = 0;
+        self.bit_cycles = 0;
+        self.awaiting_reply = false;
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    struct FixedLink(u8);
+
+    impl SerialLink for FixedLink {
+        fn exchange(&mut self, _out: u8) -> Option<u8> {
+            Some(self.0)
+        }
+    }
+
+    fn start_transfer(sc: u8, mode: GB) -> Serial {
+        let mut serial = Serial::new();
+        serial.set_link(Box::new(FixedLink(0x5A)));
+        serial.write_serial(SB, 0x42, mode);
+        serial.write_serial(SC, sc, mode);
+        serial
+    }
+
+    fn cycles_until_done(serial: &mut Serial) -> usize {
+        (1..=0x10000).find(|_| serial.tick(1)).expect("Transfer never completed")
+    }
+
+    #[test]
+    fn normal_speed_transfer_takes_1024_cycles() {
+        let mut serial = start_transfer(0x81, GB::DMG);
+        assert_eq!(cycles_until_done(&mut serial), 1024);
+        assert_eq!(serial.read_serial(SB, GB::DMG), 0x5A);
+        assert!(!serial.read_serial(SC, GB::DMG).get_bit(SC_START_BIT));
+    }
+
+    #[test]
+    fn fast_transfer_takes_32_cycles() {
+        let mut serial = start_transfer(0x83, GB::CGB);
+        assert_eq!(cycles_until_done(&mut serial), 32);
+        assert_eq!(serial.read_serial(SB, GB::CGB), 0x5A);
+    }
+
+    #[test]
+    fn speed_bit_ignored_on_dmg() {
+        let mut serial = start_transfer(0x83, GB::DMG);
+        assert_eq!(cycles_until_done(&mut serial), 1024);
+    }
+
+    struct LateLink(u8);
+
+    impl SerialLink for LateLink {
+        fn exchange(&mut self, _out: u8) -> Option<u8> {
+            None
+        }
+
+        fn poll_reply(&mut self, _m_cycles: u8) -> Option<u8> {
+            self.0 -= 1;
+            if self.0 == 0 { Some(0x5A) } else { None }
+        }
+    }
+
+    #[test]
+    fn transfer_waits_for_late_reply() {
+        let mut serial = start_transfer(0x81, GB::DMG);
+        serial.set_link(Box::new(LateLink(10)));
+        for _ in 0..(1024 + 9) {
+            assert!(!serial.tick(1));
+        }
+        assert!(serial.read_serial(SC, GB::DMG).get_bit(SC_START_BIT));
+        assert!(serial.tick(1));
+        assert_eq!(serial.read_serial(SB, GB::DMG), 0x5A);
+    }
+
+    #[test]
+    fn external_clock_waits_for_other_side() {
+        let mut serial = start_transfer(0x80, GB::DMG);
+        for _ in 0..0x1000 {
+            assert!(!serial.tick(1));
+        }
+        assert!(serial.read_serial(SC, GB::DMG).get_bit(SC_START_BIT));
+    }
+}
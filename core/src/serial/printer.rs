@@ -0,0 +1,460 @@
+// Game Boy Printer, attached via link cable
+
+/*
+ * The Game Boy sends packets to the printer, which are formatted as such:
+ *
+ * Bytes | Contents
+ * ------+---------
+ * 2     | Magic bytes, $88 $33
+ * 1     | Command
+ * 1     | Compression flag
+ * 2     | Data length, little endian
+ * N     | Data
+ * 2     | Checksum, little endian. Sum of the command through data bytes
+ * 1     | Printer replies with $81 to show it's connected
+ * 1     | Printer replies with its status
+ *
+ * Image data is sent as Game Boy tiles, 20 tiles per row.
+ * Printing uses the palette byte of the PRINT command to map colors to shades.
+ */
+
+use crate::serial::SerialLink;
+use crate::utils::{ModifyBits, SCREEN_WIDTH};
+
+use std::fs::File;
+use std::io::{self, BufWriter};
+use std::path::PathBuf;
+
+const MAGIC_1: u8 = 0x88;
+const MAGIC_2: u8 = 0x33;
+const ALIVE: u8 = 0x81;
+
+const CMD_INIT: u8 = 0x01;
+const CMD_PRINT: u8 = 0x02;
+const CMD_DATA: u8 = 0x04;
+const CMD_STATUS: u8 = 0x0F;
+
+const STATUS_CHECKSUM_ERR: u8 = 0;
+const STATUS_BUSY: u8 = 1;
+const STATUS_FULL: u8 = 2;
+const STATUS_UNPROCESSED: u8 = 3;
+
+const TILE_SIZE: usize = 8;
+const TILE_BYTES: usize = 16;
+const TILES_PER_ROW: usize = SCREEN_WIDTH / TILE_SIZE;
+const DATA_PACKET_SIZE: usize = 2 * TILES_PER_ROW * TILE_BYTES; // Each DATA packet holds two rows of tiles
+const BUFFER_SIZE: usize = 9 * DATA_PACKET_SIZE; // Enough for a full screen, 9 DATA packets
+const BUSY_STATUS_REPLIES: u8 = 4; // How many status checks report busy after printing
+
+const SHADES: [u8; 4] = [0xFF, 0xAA, 0x55, 0x00];
+const DEFAULT_PALETTE: u8 = 0xE4; // Some games send a palette of 0, which the printer treats as this
+
+#[derive(Copy, Clone, PartialEq)]
+enum PacketState {
+    Magic1,
+    Magic2,
+    Command,
+    Compression,
+    LengthLow,
+    LengthHigh,
+    Data,
+    ChecksumLow,
+    ChecksumHigh,
+    Alive,
+    Status,
+}
+
+pub struct Printer {
+    output_dir: PathBuf,
+    state: PacketState,
+    command: u8,
+    compressed: bool,
+    len: u16,
+    packet: Vec<u8>,
+    checksum: u16,
+    received_checksum: u16,
+    image: Vec<u8>,
+    status: u8,
+    busy_replies: u8,
+    num_prints: usize,
+    save_error: Option<io::Error>,
+}
+
+impl Printer {
+    /// ```
+    /// New
+    ///
+    /// Creates a new printer, which saves its printouts as PNG files
+    ///
+    /// Input:
+    ///     Directory to save printouts in (impl Into<PathBuf>)
+    /// ```
+    pub fn new<P: Into<PathBuf>>(output_dir: P) -> Printer {
+        Printer {
+            output_dir: output_dir.into(),
+            state: PacketState::Magic1,
+            command: 0,
+            compressed: false,
+            len: 0,
+            packet: Vec::new(),
+            checksum: 0,
+            received_checksum: 0,
+            image: Vec::new(),
+            status: 0,
+            busy_replies: 0,
+            num_prints: 0,
+            save_error: None,
+        }
+    }
+}
+
+// Private functions
+impl Printer {
+    /// ```
+    /// Receive
+    ///
+    /// Processes a byte sent from the Game Boy, advancing through the packet
+    ///
+    /// Input:
+    ///     Byte sent from the Game Boy (u8)
+    /// ```
+    fn receive(&mut self, byte: u8) {
+        self.state = match self.state {
+            PacketState::Magic1 => {
+                if byte == MAGIC_1 { PacketState::Magic2 } else { PacketState::Magic1 }
+            },
+            PacketState::Magic2 => {
+                if byte == MAGIC_2 { PacketState::Command } else { PacketState::Magic1 }
+            },
+            PacketState::Command => {
+                self.command = byte;
+                self.checksum = byte as u16;
+                PacketState::Compression
+            },
+            PacketState::Compression => {
+                self.compressed = byte.get_bit(0);
+                self.checksum = self.checksum.wrapping_add(byte as u16);
+                PacketState::LengthLow
+            },
+            PacketState::LengthLow => {
+                self.len = byte as u16;
+                self.checksum = self.checksum.wrapping_add(byte as u16);
+                PacketState::LengthHigh
+            },
+            PacketState::LengthHigh => {
+                self.len |= (byte as u16) << 8;
+                self.checksum = self.checksum.wrapping_add(byte as u16);
+                self.packet.clear();
+                if self.len == 0 { PacketState::ChecksumLow } else { PacketState::Data }
+            },
+            PacketState::Data => {
+                self.packet.push(byte);
+                self.checksum = self.checksum.wrapping_add(byte as u16);
+                if self.packet.len() == self.len as usize { PacketState::ChecksumLow } else { PacketState::Data }
+            },
+            PacketState::ChecksumLow => {
+                self.received_checksum = byte as u16;
+                PacketState::ChecksumHigh
+            },
+            PacketState::ChecksumHigh => {
+                self.received_checksum |= (byte as u16) << 8;
+                PacketState::Alive
+            },
+            PacketState::Alive => {
+                // The status reply reflects the packet that was just sent
+                self.run_command();
+                PacketState::Status
+            },
+            PacketState::Status => {
+                PacketState::Magic1
+            },
+        };
+    }
+
+    /// ```
+    /// Reply
+    ///
+    /// The byte the printer shifts out to the Game Boy while receiving the next one
+    ///
+    /// Output:
+    ///     Byte sent to the Game Boy (u8)
+    /// ```
+    fn reply(&self) -> u8 {
+        match self.state {
+            PacketState::Alive => { ALIVE },
+            PacketState::Status => { self.status },
+            _ => { 0x00 }
+        }
+    }
+
+    /// ```
+    /// Run command
+    ///
+    /// Performs the command of a completely received packet, and updates the status
+    /// ```
+    fn run_command(&mut self) {
+        if self.checksum != self.received_checksum {
+            self.status.set_bit(STATUS_CHECKSUM_ERR);
+            return;
+        }
+        self.status.clear_bit(STATUS_CHECKSUM_ERR);
+
+        match self.command {
+            CMD_INIT => {
+                self.image.clear();
+                self.status = 0;
+                self.busy_replies = 0;
+            },
+            CMD_DATA => {
+                let data = if self.compressed { decompress(&self.packet) } else { self.packet.clone() };
+                let space = BUFFER_SIZE - self.image.len();
+                self.image.extend(data.iter().take(space));
+                self.status.write_bit(STATUS_UNPROCESSED, !self.image.is_empty());
+                self.status.write_bit(STATUS_FULL, self.image.len() == BUFFER_SIZE);
+            },
+            CMD_PRINT => {
+                if self.packet.len() >= 4 {
+                    let palette = if self.packet[2] == 0 { DEFAULT_PALETTE } else { self.packet[2] };
+                    // Failing to save the printout shouldn't stop the game, so the frontend is left to report it
+                    if let Err(e) = self.save_png(palette) {
+                        self.save_error = Some(e);
+                    }
+                }
+                self.image.clear();
+                self.status.clear_bit(STATUS_UNPROCESSED);
+                self.status.clear_bit(STATUS_FULL);
+                self.busy_replies = BUSY_STATUS_REPLIES;
+            },
+            CMD_STATUS => {
+                // Pretend the printer takes a moment to finish printing
+                self.busy_replies = self.busy_replies.saturating_sub(1);
+            },
+            _ => {}
+        }
+
+        self.status.write_bit(STATUS_BUSY, self.busy_replies > 0);
+    }
+
+    /// ```
+    /// Save PNG
+    ///
+    /// Writes the received image data out as a grayscale PNG file
+    ///
+    /// Input:
+    ///     Palette, two bits per color, color 0 in the lowest bits (u8)
+    ///
+    /// Output:
+    ///     Whether the file could be written (io::Result<()>)
+    /// ```
+    fn save_png(&mut self, palette: u8) -> io::Result<()> {
+        let tile_rows = self.image.len() / (TILES_PER_ROW * TILE_BYTES);
+        if tile_rows == 0 {
+            return Ok(());
+        }
+
+        let width = TILES_PER_ROW * TILE_SIZE;
+        let height = tile_rows * TILE_SIZE;
+        let mut pixels = vec![0; width * height];
+
+        for (tile_index, tile) in self.image.chunks_exact(TILE_BYTES).take(tile_rows * TILES_PER_ROW).enumerate() {
+            let tile_x = (tile_index % TILES_PER_ROW) * TILE_SIZE;
+            let tile_y = (tile_index / TILES_PER_ROW) * TILE_SIZE;
+            for row in 0..TILE_SIZE {
+                let low = tile[2 * row];
+                let high = tile[2 * row + 1];
+                for col in 0..TILE_SIZE {
+                    let bit = (7 - col) as u8;
+                    let color = ((high.get_bit(bit) as u8) << 1) | (low.get_bit(bit) as u8);
+                    let shade = (palette >> (2 * color)) & 0b11;
+                    pixels[(tile_y + row) * width + tile_x + col] = SHADES[shade as usize];
+                }
+            }
+        }
+
+        self.num_prints += 1;
+        let path = self.output_dir.join(format!("print_{:03}.png", self.num_prints));
+        let file = File::create(&path).map_err(|e| io::Error::new(e.kind(), format!("{}: {}", path.display(), e)))?;
+        let mut encoder = png::Encoder::new(BufWriter::new(file), width as u32, height as u32);
+        encoder.set_color(png::ColorType::Grayscale);
+        encoder.set_depth(png::BitDepth::Eight);
+        let mut writer = encoder.write_header().map_err(io::Error::other)?;
+        writer.write_image_data(&pixels).map_err(io::Error::other)
+    }
+}
+
+impl SerialLink for Printer {
+    fn exchange(&mut self, out: u8) -> Option<u8> {
+        let reply = self.reply();
+        self.receive(out);
+        Some(reply)
+    }
+
+    fn take_error(&mut self) -> Option<io::Error> {
+        self.save_error.take()
+    }
+}
+
+/// ```
+/// Decompress
+///
+/// Expands run length encoded packet data
+/// A control byte with the high bit set repeats the following byte (N & $7F) + 2 times,
+/// otherwise the following N + 1 bytes are copied as is
+///
+/// Input:
+///     Compressed data (&[u8])
+///
+/// Output:
+///     Decompressed data (Vec<u8>)
+/// ```
+fn decompress(data: &[u8]) -> Vec<u8> {
+    let mut output = Vec::new();
+    let mut i = 0;
+    while i < data.len() {
+        let control = data[i];
+        i += 1;
+        if control.get_bit(7) {
+            let len = (control & 0x7F) as usize + 2;
+            if let Some(&byte) = data.get(i) {
+                output.extend(std::iter::repeat_n(byte, len));
+            }
+            i += 1;
+        } else {
+            let len = control as usize + 1;
+            let end = (i + len).min(data.len());
+            output.extend_from_slice(&data[i..end]);
+            i = end;
+        }
+    }
+    output
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    // Sends a whole packet, returning the printer's two reply bytes
+    fn send_packet(printer: &mut Printer, command: u8, compressed: bool, data: &[u8], checksum_error: bool) -> [u8; 2] {
+        let header = [command, compressed as u8, data.len() as u8, (data.len() >> 8) as u8];
+        let mut checksum = header.iter().chain(data).fold(0u16, |sum, &byte| sum.wrapping_add(byte as u16));
+        if checksum_error {
+            checksum = checksum.wrapping_add(1);
+        }
+
+        let mut bytes = vec![MAGIC_1, MAGIC_2];
+        bytes.extend_from_slice(&header);
+        bytes.extend_from_slice(data);
+        bytes.extend_from_slice(&[checksum as u8, (checksum >> 8) as u8, 0, 0]);
+
+        let replies: Vec<u8> = bytes.iter().map(|&byte| printer.exchange(byte).unwrap()).collect();
+        [replies[replies.len() - 2], replies[replies.len() - 1]]
+    }
+
+    fn print_dir(name: &str) -> PathBuf {
+        let dir = std::env::temp_dir().join(format!("songbird_printer_{}_{}", name, std::process::id()));
+        let _ = std::fs::remove_dir_all(&dir);
+        std::fs::create_dir_all(&dir).unwrap();
+        dir
+    }
+
+    #[test]
+    fn decompress_runs_and_literals() {
+        assert_eq!(decompress(&[0x81, 0xAA, 0x02, 0x01, 0x02, 0x03]), vec![0xAA, 0xAA, 0xAA, 0x01, 0x02, 0x03]);
+        assert_eq!(decompress(&[0x80, 0x55, 0x80, 0x66]), vec![0x55, 0x55, 0x66, 0x66]);
+        // Truncated literal runs copy what's there
+        assert_eq!(decompress(&[0x03, 0x01]), vec![0x01]);
+    }
+
+    #[test]
+    fn compressed_data_is_expanded() {
+        let mut printer = Printer::new(std::env::temp_dir());
+        send_packet(&mut printer, CMD_INIT, false, &[], false);
+        send_packet(&mut printer, CMD_DATA, true, &[0xFF, 0x12, 0x81, 0x34], false);
+        assert_eq!(printer.image.len(), (0x7F + 2) + (0x01 + 2));
+        assert!(printer.image.iter().all(|&byte| byte == 0x12 || byte == 0x34));
+    }
+
+    #[test]
+    fn replies_alive_and_status() {
+        let mut printer = Printer::new(std::env::temp_dir());
+        assert_eq!(send_packet(&mut printer, CMD_INIT, false, &[], false), [ALIVE, 0x00]);
+        let status = send_packet(&mut printer, CMD_DATA, false, &[0; DATA_PACKET_SIZE], false)[1];
+        assert!(status.get_bit(STATUS_UNPROCESSED));
+    }
+
+    #[test]
+    fn bad_checksum_is_rejected() {
+        let mut printer = Printer::new(std::env::temp_dir());
+        send_packet(&mut printer, CMD_INIT, false, &[], false);
+        let status = send_packet(&mut printer, CMD_DATA, false, &[0x12; DATA_PACKET_SIZE], true)[1];
+        assert!(status.get_bit(STATUS_CHECKSUM_ERR));
+        assert!(printer.image.is_empty());
+
+        // The next good packet clears the error
+        let status = send_packet(&mut printer, CMD_DATA, false, &[0x12; DATA_PACKET_SIZE], false)[1];
+        assert!(!status.get_bit(STATUS_CHECKSUM_ERR));
+        assert_eq!(printer.image.len(), DATA_PACKET_SIZE);
+    }
+
+    #[test]
+    fn full_after_a_screen_of_data() {
+        let mut printer = Printer::new(std::env::temp_dir());
+        send_packet(&mut printer, CMD_INIT, false, &[], false);
+        for _ in 0..8 {
+            let status = send_packet(&mut printer, CMD_DATA, false, &[0; DATA_PACKET_SIZE], false)[1];
+            assert!(!status.get_bit(STATUS_FULL));
+        }
+        let status = send_packet(&mut printer, CMD_DATA, false, &[0; DATA_PACKET_SIZE], false)[1];
+        assert!(status.get_bit(STATUS_FULL));
+    }
+
+    #[test]
+    fn zero_palette_prints_as_default() {
+        let dir = print_dir("palette");
+        let mut printer = Printer::new(&dir);
+        let data: Vec<u8> = (0..DATA_PACKET_SIZE).map(|i| i as u8).collect();
+        for palette in [0x00, DEFAULT_PALETTE, 0x1B].iter() {
+            send_packet(&mut printer, CMD_INIT, false, &[], false);
+            send_packet(&mut printer, CMD_DATA, false, &data, false);
+            send_packet(&mut printer, CMD_PRINT, false, &[0x01, 0x00, *palette, 0x40], false);
+        }
+
+        let read = |num: usize| std::fs::read(dir.join(format!("print_{:03}.png", num))).unwrap();
+        assert_eq!(read(1), read(2));
+        assert_ne!(read(2), read(3));
+        let _ = std::fs::remove_dir_all(&dir);
+    }
+
+    #[test]
+    fn consecutive_prints_numbered_in_order() {
+        let dir = print_dir("sequence");
+        let mut printer = Printer::new(&dir);
+        send_packet(&mut printer, CMD_INIT, false, &[], false);
+        send_packet(&mut printer, CMD_DATA, false, &[0x00; DATA_PACKET_SIZE], false);
+        send_packet(&mut printer, CMD_PRINT, false, &[0x01, 0x00, DEFAULT_PALETTE, 0x40], false);
+        // Nothing is left to print, so no file is written and no number is used up
+        send_packet(&mut printer, CMD_PRINT, false, &[0x01, 0x00, DEFAULT_PALETTE, 0x40], false);
+        send_packet(&mut printer, CMD_DATA, false, &[0xFF; DATA_PACKET_SIZE], false);
+        send_packet(&mut printer, CMD_PRINT, false, &[0x01, 0x00, DEFAULT_PALETTE, 0x40], false);
+
+        let path = |num: usize| dir.join(format!("print_{:03}.png", num));
+        assert!(path(1).exists());
+        assert!(path(2).exists());
+        assert!(!path(3).exists());
+        assert_ne!(std::fs::read(path(1)).unwrap(), std::fs::read(path(2)).unwrap());
+        let _ = std::fs::remove_dir_all(&dir);
+    }
+
+    #[test]
+    fn failed_save_is_reported() {
+        let dir = std::env::temp_dir().join(format!("songbird_printer_missing_{}", std::process::id()));
+        let mut printer = Printer::new(&dir);
+        assert!(printer.take_error().is_none());
+
+        send_packet(&mut printer, CMD_INIT, false, &[], false);
+        send_packet(&mut printer, CMD_DATA, false, &[0; DATA_PACKET_SIZE], false);
+        send_packet(&mut printer, CMD_PRINT, false, &[0x01, 0x00, DEFAULT_PALETTE, 0x40], false);
+        assert!(printer.take_error().is_some());
+        assert!(printer.take_error().is_none());
+    }
+}
@@ -0,0 +1,403 @@
+// Link cable over a TCP socket, to connect to another songbird process
+
+/*
+ * Every message is three bytes: kind, sequence number, data
+ *
+ * HELLO  - Sent by both sides upon connecting, data is the protocol version
+ * XFER   - Sent by the side driving the clock, data is the byte it shifted out
+ * REPLY  - Answer to an XFER with a matching sequence number, data is the other side's byte
+ * CANCEL - Withdraws the XFER with a matching sequence number, which must not be answered
+ *
+ * The side driving the clock keeps its transfer in progress until the REPLY arrives,
+ * without holding up emulation. If none arrives within REPLY_TIMEOUT_CYCLES of emulated time,
+ * it shifts in $FF (as if disconnected)
+ * and cancels the XFER, so the other side won't pick it up once it starts waiting.
+ * If both sides try to drive the clock at once, the host's transfer takes priority,
+ * and the client completes its own transfer with the host's byte instead.
+ */
+
+use crate::serial::SerialLink;
+
+use std::io::{self, Read, Write};
+use std::net::{Shutdown, TcpListener, TcpStream, ToSocketAddrs};
+use std::sync::mpsc::{channel, Receiver, TryRecvError};
+use std::thread;
+
+const PROTOCOL_VERSION: u8 = 2;
+const MSG_LEN: usize = 3;
+
+const MSG_HELLO: u8 = 0x01;
+const MSG_XFER: u8 = 0x02;
+const MSG_REPLY: u8 = 0x03;
+const MSG_CANCEL: u8 = 0x04;
+
+// How long the clock master waits for the other side before giving up on a transfer, about half a second
+// Counted in emulated machine cycles, so pausing or fast forwarding doesn't change the outcome
+const REPLY_TIMEOUT_CYCLES: u32 = 1 << 19;
+
+#[derive(Copy, Clone)]
+struct Message {
+    kind: u8,
+    seq: u8,
+    data: u8,
+}
+
+// A transfer this side has clocked out, still waiting on its REPLY
+#[derive(Copy, Clone)]
+struct Outgoing {
+    seq: u8,
+    data: u8,
+    cycles_left: u32,
+}
+
+pub struct TcpLink {
+    stream: TcpStream,
+    rx: Receiver<Message>,
+    is_host: bool,
+    seq: u8,
+    connected: bool,
+    outgoing: Option<Outgoing>,
+    incoming: Option<Message>,
+}
+
+impl TcpLink {
+    /// ```
+    /// Host
+    ///
+    /// Waits for another songbird process to connect at the given address
+    ///
+    /// Input:
+    ///     Address to listen on (impl ToSocketAddrs)
+    ///
+    /// Output:
+    ///     Connected link, or any network error (io::Result<TcpLink>)
+    /// ```
+    pub fn host<A: ToSocketAddrs>(addr: A) -> io::Result<TcpLink> {
+        let listener = TcpListener::bind(addr)?;
+        let (stream, _) = listener.accept()?;
+        TcpLink::handshake(stream, true)
+    }
+
+    /// ```
+    /// Connect
+    ///
+    /// Connects to another songbird process hosting at the given address
+    ///
+    /// Input:
+    ///     Address to connect to (impl ToSocketAddrs)
+    ///
+    /// Output:
+    ///     Connected link, or any network error (io::Result<TcpLink>)
+    /// ```
+    pub fn connect<A: ToSocketAddrs>(addr: A) -> io::Result<TcpLink> {
+        let stream = TcpStream::connect(addr)?;
+        TcpLink::handshake(stream, false)
+    }
+}
+
+// Private functions
+impl TcpLink {
+    /// ```
+    /// Handshake
+    ///
+    /// Confirms both sides speak the same protocol, then starts listening for messages
+    ///
+    /// Inputs:
+    ///     Connected socket (TcpStream)
+    ///     Whether this side is the host (bool)
+    ///
+    /// Output:
+    ///     Connected link, or any network error (io::Result<TcpLink>)
+    /// ```
+    fn handshake(mut stream: TcpStream, is_host: bool) -> io::Result<TcpLink> {
+        stream.set_nodelay(true)?;
+        stream.write_all(&[MSG_HELLO, 0, PROTOCOL_VERSION])?;
+
+        let mut hello = [0; MSG_LEN];
+        stream.read_exact(&mut hello)?;
+        if hello[0] != MSG_HELLO || hello[2] != PROTOCOL_VERSION {
+            return Err(io::Error::new(io::ErrorKind::InvalidData, "Link cable protocol mismatch"));
+        }
+
+        // Messages are read on their own thread, so the emulator never blocks on the socket unless it has to
+        let (tx, rx) = channel();
+        let mut reader = stream.try_clone()?;
+        thread::spawn(move || {
+            let mut buf = [0; MSG_LEN];
+            while reader.read_exact(&mut buf).is_ok() {
+                let msg = Message { kind: buf[0], seq: buf[1], data: buf[2] };
+                if tx.send(msg).is_err() {
+                    break;
+                }
+            }
+        });
+
+        Ok(TcpLink {
+            stream,
+            rx,
+            is_host,
+            seq: 0,
+            connected: true,
+            outgoing: None,
+            incoming: None,
+        })
+    }
+
+    /// ```
+    /// Send
+    ///
+    /// Sends a message to the other side, treating the cable as unplugged upon any error
+    ///
+    /// Inputs:
+    ///     Message type (u8)
+    ///     Sequence number (u8)
+    ///     Data byte (u8)
+    /// ```
+    fn send(&mut self, kind: u8, seq: u8, data: u8) {
+        if self.connected && self.stream.write_all(&[kind, seq, data]).is_err() {
+            self.connected = false;
+        }
+    }
+
+    /// ```
+    /// Receive
+    ///
+    /// Handles every message which has arrived since last called
+    /// The other side's latest XFER is kept until answered or cancelled
+    ///
+    /// Output:
+    ///     The other side's byte, if the REPLY to our transfer arrived (Option<u8>)
+    /// ```
+    fn receive(&mut self) -> Option<u8> {
+        let mut reply = None;
+        loop {
+            match self.rx.try_recv() {
+                Ok(msg) => {
+                    match msg.kind {
+                        MSG_XFER => {
+                            self.incoming = Some(msg);
+                        },
+                        MSG_CANCEL if self.incoming.is_some_and(|xfer| xfer.seq == msg.seq) => {
+                            self.incoming = None;
+                        },
+                        MSG_REPLY if self.outgoing.is_some_and(|xfer| xfer.seq == msg.seq) => {
+                            reply = Some(msg.data);
+                        },
+                        _ => {
+                            // Replies to transfers we've since given up on, or cancels for XFERs already replaced
+                        }
+                    }
+                },
+                Err(TryRecvError::Empty) => {
+                    return reply;
+                },
+                Err(TryRecvError::Disconnected) => {
+                    self.connected = false;
+                    return reply;
+                }
+            }
+        }
+    }
+
+    /// ```
+    /// Cancel outgoing
+    ///
+    /// Gives up on our transfer in progress, if any, so the other side won't answer it
+    /// ```
+    fn cancel_outgoing(&mut self) {
+        if let Some(xfer) = self.outgoing.take() {
+            self.send(MSG_CANCEL, xfer.seq, 0);
+        }
+    }
+}
+
+impl SerialLink for TcpLink {
+    fn exchange(&mut self, out: u8) -> Option<u8> {
+        if !self.connected {
+            return Some(0xFF);
+        }
+
+        // A new transfer means any previous one was abandoned
+        self.cancel_outgoing();
+        self.seq = self.seq.wrapping_add(1);
+        self.send(MSG_XFER, self.seq, out);
+        self.outgoing = Some(Outgoing { seq: self.seq, data: out, cycles_left: REPLY_TIMEOUT_CYCLES });
+
+        self.poll_reply(0)
+    }
+
+    fn poll_reply(&mut self, m_cycles: u8) -> Option<u8> {
+        let xfer = match self.outgoing {
+            Some(xfer) => { xfer },
+            None => { return Some(0xFF); }
+        };
+
+        if let Some(reply) = self.receive() {
+            self.outgoing = None;
+            return Some(reply);
+        }
+
+        if !self.connected {
+            self.outgoing = None;
+            return Some(0xFF);
+        }
+
+        // Both sides are driving the clock, the client defers to the host
+        if !self.is_host {
+            if let Some(other) = self.incoming.take() {
+                self.send(MSG_REPLY, other.seq, xfer.data);
+                self.cancel_outgoing();
+                return Some(other.data);
+            }
+        }
+
+        let cycles_left = xfer.cycles_left.saturating_sub(m_cycles as u32);
+        if cycles_left == 0 {
+            self.cancel_outgoing();
+            return Some(0xFF);
+        }
+        self.outgoing = Some(Outgoing { cycles_left, ..xfer });
+
+        None
+    }
+
+    fn poll_external(&mut self, out: u8) -> Option<u8> {
+        self.cancel_outgoing();
+        self.receive();
+
+        let xfer = self.incoming.take()?;
+        self.send(MSG_REPLY, xfer.seq, out);
+        Some(xfer.data)
+    }
+}
+
+impl Drop for TcpLink {
+    // The reader thread holds its own handle to the socket, so it must be shut down for the other side to see the cable unplugged
+    fn drop(&mut self) {
+        let _ = self.stream.shutdown(Shutdown::Both);
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::time::{Duration, Instant};
+
+    fn connect_pair() -> (TcpLink, TcpLink) {
+        // Find a free port, then host on it
+        let port = TcpListener::bind("127.0.0.1:0").unwrap().local_addr().unwrap().port();
+        let host = thread::spawn(move || TcpLink::host(("127.0.0.1", port)).unwrap());
+
+        let client = loop {
+            if let Ok(link) = TcpLink::connect(("127.0.0.1", port)) {
+                break link;
+            }
+            thread::sleep(Duration::from_millis(10));
+        };
+        (host.join().unwrap(), client)
+    }
+
+    // Polls the clocking side for its reply and the waiting side for its byte until both arrive
+    fn finish_transfer(clocked: &mut TcpLink, first: Option<u8>, waiting: &mut TcpLink, out: u8) -> (u8, u8) {
+        let mut reply = first;
+        let mut received = None;
+        let deadline = Instant::now() + Duration::from_secs(5);
+        while reply.is_none() || received.is_none() {
+            assert!(Instant::now() < deadline, "Transfer never completed");
+            if received.is_none() {
+                received = waiting.poll_external(out);
+            }
+            if reply.is_none() {
+                reply = clocked.poll_reply(1);
+            }
+        }
+        (reply.unwrap(), received.unwrap())
+    }
+
+    #[test]
+    fn exchange_over_loopback() {
+        let (mut host, mut client) = connect_pair();
+
+        let first = host.exchange(0x42);
+        assert_eq!(finish_transfer(&mut host, first, &mut client, 0x99), (0x99, 0x42));
+
+        let first = client.exchange(0x13);
+        assert_eq!(finish_transfer(&mut client, first, &mut host, 0x37), (0x37, 0x13));
+    }
+
+    #[test]
+    fn exchange_does_not_block() {
+        let (mut host, _client) = connect_pair();
+
+        assert_eq!(host.exchange(0x42), None);
+        assert_eq!(host.poll_reply(1), None);
+    }
+
+    #[test]
+    fn timed_out_transfer_is_dropped() {
+        let (mut host, mut client) = connect_pair();
+
+        // Nobody is waiting on the other end, so the transfer times out after exactly REPLY_TIMEOUT_CYCLES
+        assert_eq!(host.exchange(0x42), None);
+        for _ in 1..REPLY_TIMEOUT_CYCLES {
+            assert_eq!(host.poll_reply(1), None);
+        }
+        assert_eq!(host.poll_reply(1), Some(0xFF));
+
+        // Once the client starts waiting, it must not pick up the stale byte
+        thread::sleep(Duration::from_millis(50));
+        for _ in 0..10 {
+            assert_eq!(client.poll_external(0x99), None);
+        }
+
+        let first = host.exchange(0x24);
+        assert_eq!(finish_transfer(&mut host, first, &mut client, 0x99), (0x99, 0x24));
+    }
+
+    #[test]
+    fn host_wins_simultaneous_transfers() {
+        let (mut host, mut client) = connect_pair();
+
+        let mut host_reply = host.exchange(0x11);
+        let mut client_reply = client.exchange(0x22);
+        let deadline = Instant::now() + Duration::from_secs(5);
+        while host_reply.is_none() || client_reply.is_none() {
+            assert!(Instant::now() < deadline, "Transfer never completed");
+            if host_reply.is_none() {
+                host_reply = host.poll_reply(1);
+            }
+            if client_reply.is_none() {
+                client_reply = client.poll_reply(1);
+            }
+        }
+        assert_eq!((host_reply, client_reply), (Some(0x22), Some(0x11)));
+
+        // The client's abandoned transfer isn't answered by the host later
+        thread::sleep(Duration::from_millis(50));
+        assert_eq!(host.poll_external(0x33), None);
+    }
+
+    #[test]
+    fn timeout_ignores_wall_clock() {
+        let (mut host, _client) = connect_pair();
+
+        // While emulation is paused, no time passes for the transfer
+        assert_eq!(host.exchange(0x42), None);
+        thread::sleep(Duration::from_millis(50));
+        assert_eq!(host.poll_reply(0), None);
+    }
+
+    #[test]
+    fn dropped_link_disconnects() {
+        let (mut host, client) = connect_pair();
+        drop(client);
+
+        // The other side sees the cable unplugged without having to send anything first
+        let deadline = Instant::now() + Duration::from_secs(5);
+        while host.connected {
+            assert!(Instant::now() < deadline, "Disconnect never noticed");
+            assert_eq!(host.poll_external(0x42), None);
+        }
+        assert_eq!(host.exchange(0x42), Some(0xFF));
+    }
+}
@@ -0,0 +1,204 @@
+// Super Game Boy border
+
+/*
+ * The border is a 32x28 map of 8x8 SNES tiles, drawn around the Game Boy screen.
+ * Tiles are 4 bits per pixel, 32 bytes each. The first 16 bytes hold bitplanes 0 and 1,
+ * interleaved by row, the last 16 hold bitplanes 2 and 3. Color 0 is transparent.
+ *
+ * Each map entry is two bytes:
+ *
+ * Bit   | Function
+ * ------+---------
+ * 15    | Y flip
+ * 14    | X flip
+ * 13    | Unused
+ * 12-10 | Palette number (4-7)
+ * 9-8   | Unused
+ * 7-0   | Tile number
+ */
+
+use crate::ppu::palette::gbc2rgba;
+use crate::utils::*;
+
+// Constants
+const BORDER_TILE_NUM: usize = 256;
+const BORDER_TILE_BYTES: usize = 32;
+const BORDER_BANK_SIZE: usize = BORDER_TILE_NUM * BORDER_TILE_BYTES / 2;
+const BORDER_MAP_WIDTH: usize = SGB_SCREEN_WIDTH / TILESIZE;
+const BORDER_MAP_HEIGHT: usize = SGB_SCREEN_HEIGHT / TILESIZE;
+const BORDER_MAP_BYTES: usize = 0x800; // 32x32 entries are sent, but only 28 rows are shown
+const BORDER_PAL_NUM: usize = 4;
+const BORDER_PAL_SIZE: usize = 16;
+
+const Y_FLIP_BIT: u8 = 15;
+const X_FLIP_BIT: u8 = 14;
+
+pub struct Border {
+    tiles: [u8; BORDER_TILE_NUM * BORDER_TILE_BYTES],
+    map: [u8; BORDER_MAP_BYTES],
+    palettes: [u16; BORDER_PAL_NUM * BORDER_PAL_SIZE],
+}
+
+impl Default for Border {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+impl Border {
+    pub fn new() -> Border {
+        Border {
+            tiles: [0; BORDER_TILE_NUM * BORDER_TILE_BYTES],
+            map: [0; BORDER_MAP_BYTES],
+            palettes: [0; BORDER_PAL_NUM * BORDER_PAL_SIZE],
+        }
+    }
+
+    /// ```
+    /// Set tiles
+    ///
+    /// Stores half of the border tiles, as sent by CHR_TRN
+    ///
+    /// Inputs:
+    ///     Which half of the tiles, 0 for $00-$7F, 1 for $80-$FF (usize)
+    ///     Transferred data (&[u8])
+    /// ```
+    pub fn set_tiles(&mut self, bank: usize, data: &[u8]) {
+        let start = bank * BORDER_BANK_SIZE;
+        self.tiles[start..(start + BORDER_BANK_SIZE)].copy_from_slice(&data[..BORDER_BANK_SIZE]);
+    }
+
+    /// ```
+    /// Set map
+    ///
+    /// Stores the border map, followed by its palettes, as sent by PCT_TRN
+    ///
+    /// Input:
+    ///     Transferred data (&[u8])
+    /// ```
+    pub fn set_map(&mut self, data: &[u8]) {
+        self.map.copy_from_slice(&data[..BORDER_MAP_BYTES]);
+        for (i, color) in self.palettes.iter_mut().enumerate() {
+            let addr = BORDER_MAP_BYTES + 2 * i;
+            *color = merge_bytes(data[addr + 1], data[addr]);
+        }
+    }
+
+    /// ```
+    /// Render
+    ///
+    /// Draws the opaque pixels of the border
+    ///
+    /// Input:
+    ///     Buffer of SGB_SCREEN_WIDTH x SGB_SCREEN_HEIGHT pixels to draw into (&mut [u8])
+    /// ```
+    pub fn render(&self, buffer: &mut [u8]) {
+        for map_y in 0..BORDER_MAP_HEIGHT {
+            for map_x in 0..BORDER_MAP_WIDTH {
+                let idx = 2 * (map_y * BORDER_MAP_WIDTH + map_x);
+                let entry = merge_bytes(self.map[idx + 1], self.map[idx]);
+                let tile = &self.tiles[(entry.get_low_byte() as usize * BORDER_TILE_BYTES)..][..BORDER_TILE_BYTES];
+                // Only palettes 4-7 are available to the border
+                let pal = ((entry >> 10) & 0b11) as usize * BORDER_PAL_SIZE;
+
+                for row in 0..TILESIZE {
+                    let tile_row = if entry.get_bit(Y_FLIP_BIT) { TILESIZE - row - 1 } else { row };
+                    for col in 0..TILESIZE {
+                        let bit = if entry.get_bit(X_FLIP_BIT) { col } else { TILESIZE - col - 1 } as u8;
+                        let mut color = 0;
+                        for (plane, byte) in [2 * tile_row, 2 * tile_row + 1, 2 * tile_row + 16, 2 * tile_row + 17].iter().enumerate() {
+                            color |= (tile[*byte].get_bit(bit) as usize) << plane;
+                        }
+
+                        if color != 0 {
+                            let gbc = self.palettes[pal + color];
+                            let pixel = ((map_y * TILESIZE + row) * SGB_SCREEN_WIDTH) + (map_x * TILESIZE + col);
+                            buffer[(COLOR_CHANNELS * pixel)..(COLOR_CHANNELS * (pixel + 1))].copy_from_slice(&gbc2rgba(gbc.get_low_byte(), gbc.get_high_byte()));
+                        }
+                    }
+                }
+            }
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    const RED: u16 = 0x001F;
+    const BLUE: u16 = 0x7C00;
+
+    // Border with tile 1 holding a single color 1 pixel in its top left, and palettes 4 and 5
+    // using red and blue for color 1
+    fn make_border(entry: u16) -> Border {
+        let mut border = Border::new();
+        let mut tiles = vec![0; BORDER_BANK_SIZE];
+        tiles[BORDER_TILE_BYTES] = 0x80;
+        border.set_tiles(0, &tiles);
+
+        let mut map = vec![0; BORDER_MAP_BYTES + 2 * BORDER_PAL_NUM * BORDER_PAL_SIZE];
+        map[0] = entry.get_low_byte();
+        map[1] = entry.get_high_byte();
+        for (pal, color) in [(0, RED), (1, BLUE)].iter() {
+            let addr = BORDER_MAP_BYTES + 2 * (pal * BORDER_PAL_SIZE + 1);
+            map[addr] = color.get_low_byte();
+            map[addr + 1] = color.get_high_byte();
+        }
+        border.set_map(&map);
+        border
+    }
+
+    // Finds the only opaque pixel drawn in the top left tile
+    fn drawn_pixel(border: &Border) -> (usize, usize, [u8; COLOR_CHANNELS]) {
+        let mut buffer = vec![0; SGB_DISP_SIZE];
+        border.render(&mut buffer);
+        let mut drawn = Vec::new();
+        for y in 0..TILESIZE {
+            for x in 0..TILESIZE {
+                let pixel = &buffer[((y * SGB_SCREEN_WIDTH + x) * COLOR_CHANNELS)..][..COLOR_CHANNELS];
+                if pixel.iter().any(|&channel| channel != 0) {
+                    drawn.push((x, y, [pixel[0], pixel[1], pixel[2], pixel[3]]));
+                }
+            }
+        }
+        assert_eq!(drawn.len(), 1);
+        drawn[0]
+    }
+
+    fn rgba(color: u16) -> [u8; COLOR_CHANNELS] {
+        gbc2rgba(color.get_low_byte(), color.get_high_byte())
+    }
+
+    #[test]
+    fn decodes_palette() {
+        assert_eq!(drawn_pixel(&make_border(0x0001 | (4 << 10))), (0, 0, rgba(RED)));
+        assert_eq!(drawn_pixel(&make_border(0x0001 | (5 << 10))), (0, 0, rgba(BLUE)));
+    }
+
+    #[test]
+    fn decodes_flips() {
+        let entry = 0x0001 | (4 << 10);
+        assert_eq!(drawn_pixel(&make_border(entry | (1 << X_FLIP_BIT))), (7, 0, rgba(RED)));
+        assert_eq!(drawn_pixel(&make_border(entry | (1 << Y_FLIP_BIT))), (0, 7, rgba(RED)));
+        assert_eq!(drawn_pixel(&make_border(entry | (1 << X_FLIP_BIT) | (1 << Y_FLIP_BIT))), (7, 7, rgba(RED)));
+    }
+
+    #[test]
+    fn color_0_is_transparent() {
+        // Tile 0 is left empty
+        let border = make_border(4 << 10);
+        let mut buffer = vec![0; SGB_DISP_SIZE];
+        border.render(&mut buffer);
+        assert!(buffer.iter().all(|&channel| channel == 0));
+    }
+
+    #[test]
+    fn second_bank_holds_upper_tiles() {
+        let mut border = make_border(0x0081 | (4 << 10));
+        let mut tiles = vec![0; BORDER_BANK_SIZE];
+        tiles[BORDER_TILE_BYTES] = 0x80;
+        border.set_tiles(1, &tiles);
+        assert_eq!(drawn_pixel(&border), (0, 0, rgba(RED)));
+    }
+}
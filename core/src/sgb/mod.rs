@@ -0,0 +1,818 @@
+// Super Game Boy functions
+
+/*
+ * Super Game Boy
+ * Games send commands to the SGB through the joypad register ($FF00), one bit per pulse
+ *
+ * P15 | P14 | Meaning
+ * ----+-----+--------
+ * 0   | 0   | Reset, begins a packet
+ * 0   | 1   | "1" bit
+ * 1   | 0   | "0" bit
+ * 1   | 1   | End of pulse
+ *
+ * Packets are 16 bytes, sent LSB first, followed by a "0" stop bit. The first byte of a command
+ * holds the command number in bits 7-3 and how many packets it spans in bits 2-0.
+ *
+ * The screen is colorized in 8x8 blocks, each using one of four palettes. Commands which send more
+ * data than fits in a packet (*_TRN) instead take 4 KiB from the next frame shown on screen, read
+ * back as tile data from the top left, 20 tiles per row.
+ */
+
+pub mod border;
+
+use border::Border;
+use crate::ppu::palette::{gbc2rgba, DMG_PAL_SIZE};
+use crate::utils::*;
+
+use std::cmp::Ordering;
+
+// =============
+// = Constants =
+// =============
+const PACKET_SIZE: usize = 16;
+const PACKET_BITS: usize = PACKET_SIZE * 8;
+const TRN_SIZE: usize = 0x1000;
+
+const ATTR_MAP_WIDTH: usize = SCREEN_WIDTH / TILESIZE;
+const ATTR_MAP_HEIGHT: usize = SCREEN_HEIGHT / TILESIZE;
+const ATTR_MAP_SIZE: usize = ATTR_MAP_WIDTH * ATTR_MAP_HEIGHT;
+const ATTR_FILE_NUM: usize = 45;
+const ATTR_FILE_SIZE: usize = ATTR_MAP_SIZE / 4;
+const SGB_PAL_NUM: usize = 4;
+const SYS_PAL_NUM: usize = 512;
+
+// Position of the Game Boy screen within the border
+const SCREEN_X: usize = (SGB_SCREEN_WIDTH - SCREEN_WIDTH) / 2;
+const SCREEN_Y: usize = (SGB_SCREEN_HEIGHT - SCREEN_HEIGHT) / 2;
+
+// Used until the game sets its own palettes
+const DEFAULT_PAL: [u16; DMG_PAL_SIZE] = [0x67BF, 0x265B, 0x10B5, 0x2866];
+
+// Commands
+const PAL01: u8     = 0x00;
+const PAL23: u8     = 0x01;
+const PAL03: u8     = 0x02;
+const PAL12: u8     = 0x03;
+const ATTR_BLK: u8  = 0x04;
+const ATTR_LIN: u8  = 0x05;
+const ATTR_DIV: u8  = 0x06;
+const ATTR_CHR: u8  = 0x07;
+const PAL_SET: u8   = 0x0A;
+const PAL_TRN: u8   = 0x0B;
+const MLT_REQ: u8   = 0x11;
+const CHR_TRN: u8   = 0x13;
+const PCT_TRN: u8   = 0x14;
+const ATTR_TRN: u8  = 0x15;
+const ATTR_SET: u8  = 0x16;
+const MASK_EN: u8   = 0x17;
+
+#[derive(Copy, Clone, PartialEq)]
+enum Transfer {
+    Palettes,
+    Attributes,
+    Tiles(usize),
+    Border,
+}
+
+#[derive(Copy, Clone, PartialEq)]
+enum Mask {
+    Off,
+    Freeze,
+    Black,
+    Color0,
+}
+
+pub struct SGB {
+    packet: [u8; PACKET_SIZE],
+    packet_bit: Option<usize>,
+    pulse_ended: bool,
+    command: Vec<u8>,
+    players: u8,
+    player: u8,
+    polled: u8,
+    palettes: [[u16; DMG_PAL_SIZE]; SGB_PAL_NUM],
+    sys_palettes: [[u16; DMG_PAL_SIZE]; SYS_PAL_NUM],
+    attr_map: [u8; ATTR_MAP_SIZE],
+    attr_files: [u8; ATTR_FILE_NUM * ATTR_FILE_SIZE],
+    mask: Mask,
+    transfer: Option<Transfer>,
+    screen: [u8; SCREEN_WIDTH * SCREEN_HEIGHT],
+    border: Border,
+}
+
+impl Default for SGB {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+impl SGB {
+    pub fn new() -> SGB {
+        SGB {
+            packet: [0; PACKET_SIZE],
+            packet_bit: None,
+            pulse_ended: false,
+            command: Vec::new(),
+            players: 1,
+            player: 0,
+            polled: 0,
+            palettes: [DEFAULT_PAL; SGB_PAL_NUM],
+            sys_palettes: [[0; DMG_PAL_SIZE]; SYS_PAL_NUM],
+            attr_map: [0; ATTR_MAP_SIZE],
+            attr_files: [0; ATTR_FILE_NUM * ATTR_FILE_SIZE],
+            mask: Mask::Off,
+            transfer: None,
+            screen: [0; SCREEN_WIDTH * SCREEN_HEIGHT],
+            border: Border::new(),
+        }
+    }
+
+    /// ```
+    /// Write joypad
+    ///
+    /// Receives packet bits from writes to the joypad register
+    ///
+    /// Input:
+    ///     Value written to $FF00 (u8)
+    /// ```
+    pub fn write_joypad(&mut self, val: u8) {
+        let lines = (val >> 4) & 0b11;
+        match lines {
+            0b00 => {
+                self.packet = [0; PACKET_SIZE];
+                self.packet_bit = Some(0);
+                self.pulse_ended = false;
+            },
+            0b11 => {
+                self.pulse_ended = true;
+
+                // With multiple players, the next controller is selected once both button groups have been read
+                if self.packet_bit.is_none() && self.polled == 0b11 {
+                    self.player = (self.player + 1) % self.players;
+                    self.polled = 0;
+                }
+            },
+            _ => {
+                match self.packet_bit {
+                    Some(bit) if self.pulse_ended => {
+                        self.pulse_ended = false;
+                        self.receive_bit(bit, lines == 0b01);
+                    },
+                    None => { self.polled |= !lines & 0b11 },
+                    _ => {}
+                }
+            }
+        }
+    }
+
+    /// ```
+    /// Read joypad
+    ///
+    /// Adjusts the joypad register for multiplayer mode
+    /// Only the first player's buttons are connected, and the controller ID is read while neither group is selected
+    ///
+    /// Input:
+    ///     Value of $FF00 from the Game Boy's joypad (u8)
+    ///
+    /// Output:
+    ///     Value of $FF00 seen by the game (u8)
+    /// ```
+    pub fn read_joypad(&self, btns: u8) -> u8 {
+        if self.players > 1 && (btns & 0b0011_0000) == 0b0011_0000 {
+            (btns & 0xF0) | (0x0F - self.player)
+        } else if self.player != 0 {
+            btns | 0x0F
+        } else {
+            btns
+        }
+    }
+
+    /// ```
+    /// Update frame
+    ///
+    /// Takes a newly finished frame from the Game Boy, completing any pending data transfer
+    ///
+    /// Input:
+    ///     DMG shade of each pixel of the screen (&[u8])
+    /// ```
+    pub fn update_frame(&mut self, shades: &[u8]) {
+        if let Some(transfer) = self.transfer.take() {
+            let data = read_vram_data(shades);
+            match transfer {
+                Transfer::Palettes => {
+                    for (i, pal) in self.sys_palettes.iter_mut().enumerate() {
+                        for (j, color) in pal.iter_mut().enumerate() {
+                            let addr = 2 * (i * DMG_PAL_SIZE + j);
+                            *color = merge_bytes(data[addr + 1], data[addr]);
+                        }
+                    }
+                },
+                Transfer::Attributes => {
+                    self.attr_files.copy_from_slice(&data[..(ATTR_FILE_NUM * ATTR_FILE_SIZE)]);
+                },
+                Transfer::Tiles(bank) => {
+                    self.border.set_tiles(bank, &data);
+                },
+                Transfer::Border => {
+                    self.border.set_map(&data);
+                }
+            }
+        }
+
+        if self.mask != Mask::Freeze {
+            self.screen.copy_from_slice(shades);
+        }
+    }
+
+    /// ```
+    /// Render screen
+    ///
+    /// Colorizes the Game Boy screen
+    ///
+    /// Output:
+    ///     Array of pixels to draw ([u8])
+    /// ```
+    pub fn render_screen(&self) -> [u8; DISP_SIZE] {
+        let mut colors = [[[0; COLOR_CHANNELS]; DMG_PAL_SIZE]; SGB_PAL_NUM];
+        for (pal, rgba) in self.palettes.iter().zip(colors.iter_mut()) {
+            for (color, pixel) in pal.iter().zip(rgba.iter_mut()) {
+                *pixel = match self.mask {
+                    Mask::Black => { [0, 0, 0, 0xFF] },
+                    Mask::Color0 => { gbc2rgba(self.palettes[0][0].get_low_byte(), self.palettes[0][0].get_high_byte()) },
+                    _ => { gbc2rgba(color.get_low_byte(), color.get_high_byte()) }
+                };
+            }
+        }
+
+        let mut screen = [0xFF; DISP_SIZE];
+        for (i, shade) in self.screen.iter().enumerate() {
+            let x = i % SCREEN_WIDTH;
+            let y = i / SCREEN_WIDTH;
+            let pal = self.attr_map[(y / TILESIZE) * ATTR_MAP_WIDTH + (x / TILESIZE)] as usize;
+            screen[(COLOR_CHANNELS * i)..(COLOR_CHANNELS * (i + 1))].copy_from_slice(&colors[pal][*shade as usize]);
+        }
+        screen
+    }
+
+    /// ```
+    /// Render border
+    ///
+    /// Draws the Game Boy screen within the border
+    ///
+    /// Input:
+    ///     Game Boy screen (&[u8])
+    ///
+    /// Output:
+    ///     Array of pixels to draw ([u8])
+    /// ```
+    pub fn render_border(&self, screen: &[u8]) -> [u8; SGB_DISP_SIZE] {
+        // Transparent border pixels show the shared background color
+        let backdrop = gbc2rgba(self.palettes[0][0].get_low_byte(), self.palettes[0][0].get_high_byte());
+        let mut buffer = [0; SGB_DISP_SIZE];
+        for pixel in buffer.chunks_exact_mut(COLOR_CHANNELS) {
+            pixel.copy_from_slice(&backdrop);
+        }
+        self.border.render(&mut buffer);
+
+        let row_size = SCREEN_WIDTH * COLOR_CHANNELS;
+        for (y, row) in screen.chunks_exact(row_size).enumerate() {
+            let start = ((SCREEN_Y + y) * SGB_SCREEN_WIDTH + SCREEN_X) * COLOR_CHANNELS;
+            buffer[start..(start + row_size)].copy_from_slice(row);
+        }
+        buffer
+    }
+
+    // ===================
+    // = Private methods =
+    // ===================
+
+    /// ```
+    /// Receive bit
+    ///
+    /// Stores the next bit of the current packet
+    ///
+    /// Inputs:
+    ///     Index of bit in packet (usize)
+    ///     Bit value (bool)
+    /// ```
+    fn receive_bit(&mut self, bit: usize, val: bool) {
+        if bit == PACKET_BITS {
+            // Packets missing their stop bit are discarded
+            self.packet_bit = None;
+            if !val {
+                self.receive_packet();
+            }
+            return;
+        }
+
+        self.packet[bit / 8].write_bit((bit % 8) as u8, val);
+        self.packet_bit = Some(bit + 1);
+    }
+
+    /// ```
+    /// Receive packet
+    ///
+    /// Adds a finished packet to the current command, running it once all its packets have arrived
+    /// ```
+    fn receive_packet(&mut self) {
+        let len = if self.command.is_empty() { self.packet[0] } else { self.command[0] } & 0b111;
+        if len == 0 {
+            return;
+        }
+
+        self.command.extend_from_slice(&self.packet);
+        if self.command.len() >= len as usize * PACKET_SIZE {
+            let command = std::mem::take(&mut self.command);
+            self.run_command(&command);
+        }
+    }
+
+    /// ```
+    /// Run command
+    ///
+    /// Carries out a completely received command
+    /// Sound, SNES and system commands are ignored
+    ///
+    /// Input:
+    ///     Command packets (&[u8])
+    /// ```
+    fn run_command(&mut self, data: &[u8]) {
+        match data[0] >> 3 {
+            PAL01 =>    { self.set_palettes(0, 1, data) },
+            PAL23 =>    { self.set_palettes(2, 3, data) },
+            PAL03 =>    { self.set_palettes(0, 3, data) },
+            PAL12 =>    { self.set_palettes(1, 2, data) },
+            ATTR_BLK => { self.attr_blk(data) },
+            ATTR_LIN => { self.attr_lin(data) },
+            ATTR_DIV => { self.attr_div(data) },
+            ATTR_CHR => { self.attr_chr(data) },
+            PAL_SET =>  { self.pal_set(data) },
+            PAL_TRN =>  { self.transfer = Some(Transfer::Palettes) },
+            MLT_REQ => {
+                self.players = match data[1] & 0b11 {
+                    0b01 => { 2 },
+                    0b11 => { 4 },
+                    _ =>    { 1 }
+                };
+                self.player = 0;
+            },
+            CHR_TRN =>  { self.transfer = Some(Transfer::Tiles((data[1] & 0b1) as usize)) },
+            PCT_TRN =>  { self.transfer = Some(Transfer::Border) },
+            ATTR_TRN => { self.transfer = Some(Transfer::Attributes) },
+            ATTR_SET => {
+                self.set_attr_file(data[1] & 0x3F);
+                if data[1].get_bit(6) {
+                    self.mask = Mask::Off;
+                }
+            },
+            MASK_EN => {
+                self.mask = match data[1] & 0b11 {
+                    1 => { Mask::Freeze },
+                    2 => { Mask::Black },
+                    3 => { Mask::Color0 },
+                    _ => { Mask::Off }
+                };
+            },
+            _ => {}
+        }
+    }
+
+    /// ```
+    /// Set palettes
+    ///
+    /// Handles PAL01, PAL23, PAL03 and PAL12, which set colors 1-3 of two palettes,
+    /// along with color 0, which is shared by all palettes
+    ///
+    /// Inputs:
+    ///     First palette (usize)
+    ///     Second palette (usize)
+    ///     Command packets (&[u8])
+    /// ```
+    fn set_palettes(&mut self, first: usize, second: usize, data: &[u8]) {
+        let color = |i: usize| merge_bytes(data[2 * i + 2], data[2 * i + 1]);
+        for pal in self.palettes.iter_mut() {
+            pal[0] = color(0);
+        }
+        for i in 1..DMG_PAL_SIZE {
+            self.palettes[first][i] = color(i);
+            self.palettes[second][i] = color(i + DMG_PAL_SIZE - 1);
+        }
+    }
+
+    /// ```
+    /// ATTR_BLK
+    ///
+    /// Sets the palettes inside, outside and on the edge of rectangles
+    ///
+    /// Input:
+    ///     Command packets (&[u8])
+    /// ```
+    fn attr_blk(&mut self, data: &[u8]) {
+        let num_sets = data[1] as usize;
+        for set in data[2..].chunks_exact(6).take(num_sets) {
+            let ctrl = set[0] & 0b111;
+            let inside = set[1] & 0b11;
+            let outside = (set[1] >> 4) & 0b11;
+            // If only the inside or outside is changed, the edge goes along with it
+            let edge = match ctrl {
+                0b001 => { Some(inside) },
+                0b100 => { Some(outside) },
+                _ if ctrl.get_bit(1) => { Some((set[1] >> 2) & 0b11) },
+                _ => { None }
+            };
+            let (x1, y1, x2, y2) = ((set[2] & 0x1F) as usize, (set[3] & 0x1F) as usize, (set[4] & 0x1F) as usize, (set[5] & 0x1F) as usize);
+
+            for y in 0..ATTR_MAP_HEIGHT {
+                for x in 0..ATTR_MAP_WIDTH {
+                    let pal = if x > x1 && x < x2 && y > y1 && y < y2 {
+                        if ctrl.get_bit(0) { Some(inside) } else { None }
+                    } else if x >= x1 && x <= x2 && y >= y1 && y <= y2 {
+                        edge
+                    } else if ctrl.get_bit(2) {
+                        Some(outside)
+                    } else {
+                        None
+                    };
+
+                    if let Some(pal) = pal {
+                        self.attr_map[y * ATTR_MAP_WIDTH + x] = pal;
+                    }
+                }
+            }
+        }
+    }
+
+    /// ```
+    /// ATTR_LIN
+    ///
+    /// Sets the palettes of whole rows or columns
+    ///
+    /// Input:
+    ///     Command packets (&[u8])
+    /// ```
+    fn attr_lin(&mut self, data: &[u8]) {
+        let num_lines = data[1] as usize;
+        for line in data[2..].iter().take(num_lines) {
+            let num = (line & 0x1F) as usize;
+            let pal = (line >> 5) & 0b11;
+            if line.get_bit(7) {
+                if num < ATTR_MAP_HEIGHT {
+                    self.attr_map[(num * ATTR_MAP_WIDTH)..((num + 1) * ATTR_MAP_WIDTH)].fill(pal);
+                }
+            } else if num < ATTR_MAP_WIDTH {
+                for y in 0..ATTR_MAP_HEIGHT {
+                    self.attr_map[y * ATTR_MAP_WIDTH + num] = pal;
+                }
+            }
+        }
+    }
+
+    /// ```
+    /// ATTR_DIV
+    ///
+    /// Splits the screen in two along a line, setting the palettes of each side and of the line itself
+    ///
+    /// Input:
+    ///     Command packets (&[u8])
+    /// ```
+    fn attr_div(&mut self, data: &[u8]) {
+        let after = data[1] & 0b11;
+        let before = (data[1] >> 2) & 0b11;
+        let on_line = (data[1] >> 4) & 0b11;
+        let horizontal = data[1].get_bit(6);
+        let line = (data[2] & 0x1F) as usize;
+
+        for y in 0..ATTR_MAP_HEIGHT {
+            for x in 0..ATTR_MAP_WIDTH {
+                let pos = if horizontal { y } else { x };
+                self.attr_map[y * ATTR_MAP_WIDTH + x] = match pos.cmp(&line) {
+                    Ordering::Less =>    { before },
+                    Ordering::Equal =>   { on_line },
+                    Ordering::Greater => { after }
+                };
+            }
+        }
+    }
+
+    /// ```
+    /// ATTR_CHR
+    ///
+    /// Sets the palettes of individual blocks, starting at a given block and moving across or down
+    ///
+    /// Input:
+    ///     Command packets (&[u8])
+    /// ```
+    fn attr_chr(&mut self, data: &[u8]) {
+        let mut x = data[1] as usize;
+        let mut y = data[2] as usize;
+        let num_blocks = merge_bytes(data[4], data[3]) as usize;
+        let vertical = data[5].get_bit(0);
+
+        if x >= ATTR_MAP_WIDTH || y >= ATTR_MAP_HEIGHT {
+            return;
+        }
+
+        // Four blocks are packed into each byte, starting from the upper bits
+        // Going past the end of the screen wraps back around to the top left
+        for i in 0..num_blocks.min(ATTR_MAP_SIZE) {
+            let byte = match data.get(6 + i / 4) {
+                Some(byte) => { byte },
+                None => { break }
+            };
+            self.attr_map[y * ATTR_MAP_WIDTH + x] = (byte >> (6 - 2 * (i % 4))) & 0b11;
+
+            if vertical {
+                y += 1;
+                if y == ATTR_MAP_HEIGHT {
+                    y = 0;
+                    x = (x + 1) % ATTR_MAP_WIDTH;
+                }
+            } else {
+                x += 1;
+                if x == ATTR_MAP_WIDTH {
+                    x = 0;
+                    y = (y + 1) % ATTR_MAP_HEIGHT;
+                }
+            }
+        }
+    }
+
+    /// ```
+    /// PAL_SET
+    ///
+    /// Copies four of the system palettes sent by PAL_TRN into the screen palettes
+    ///
+    /// Input:
+    ///     Command packets (&[u8])
+    /// ```
+    fn pal_set(&mut self, data: &[u8]) {
+        for i in 0..SGB_PAL_NUM {
+            let num = merge_bytes(data[2 * i + 2], data[2 * i + 1]) as usize % SYS_PAL_NUM;
+            self.palettes[i] = self.sys_palettes[num];
+        }
+
+        // Color 0 of the first palette is used by all of them
+        let color0 = self.palettes[0][0];
+        for pal in self.palettes.iter_mut() {
+            pal[0] = color0;
+        }
+
+        let flags = data[9];
+        if flags.get_bit(7) {
+            self.set_attr_file(flags & 0x3F);
+        }
+        if flags.get_bit(6) {
+            self.mask = Mask::Off;
+        }
+    }
+
+    /// ```
+    /// Set attribute file
+    ///
+    /// Replaces the palette of every block with one of the layouts sent by ATTR_TRN
+    ///
+    /// Input:
+    ///     Attribute file number (u8)
+    /// ```
+    fn set_attr_file(&mut self, num: u8) {
+        let num = num as usize;
+        if num >= ATTR_FILE_NUM {
+            return;
+        }
+
+        let file = &self.attr_files[(num * ATTR_FILE_SIZE)..((num + 1) * ATTR_FILE_SIZE)];
+        for (i, pal) in self.attr_map.iter_mut().enumerate() {
+            *pal = (file[i / 4] >> (6 - 2 * (i % 4))) & 0b11;
+        }
+    }
+}
+
+/// ```
+/// Read VRAM data
+///
+/// Recovers the data a *_TRN command sends by displaying it as tiles on screen
+///
+/// Input:
+///     DMG shade of each pixel of the screen (&[u8])
+///
+/// Output:
+///     Transferred data (Vec<u8>)
+/// ```
+fn read_vram_data(shades: &[u8]) -> Vec<u8> {
+    let mut data = vec![0; TRN_SIZE];
+    for (i, row_bytes) in data.chunks_exact_mut(2).enumerate() {
+        let tile = i / TILESIZE;
+        let row = i % TILESIZE;
+        let y = (tile / ATTR_MAP_WIDTH) * TILESIZE + row;
+        let x = (tile % ATTR_MAP_WIDTH) * TILESIZE;
+
+        for col in 0..TILESIZE {
+            let shade = shades[y * SCREEN_WIDTH + x + col];
+            let bit = (TILESIZE - col - 1) as u8;
+            row_bytes[0].write_bit(bit, shade.get_bit(0));
+            row_bytes[1].write_bit(bit, shade.get_bit(1));
+        }
+    }
+    data
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    // Pulses a command into the SGB through the joypad register, one packet at a time
+    fn send_command(sgb: &mut SGB, data: &[u8]) {
+        for packet in data.chunks(PACKET_SIZE) {
+            sgb.write_joypad(0x00);
+            sgb.write_joypad(0x30);
+            let bits = (0..PACKET_BITS).map(|bit| packet.get(bit / 8).is_some_and(|byte| byte.get_bit((bit % 8) as u8)));
+            for bit in bits.chain(std::iter::once(false)) {
+                sgb.write_joypad(if bit { 0x10 } else { 0x20 });
+                sgb.write_joypad(0x30);
+            }
+        }
+    }
+
+    fn command(cmd: u8, args: &[u8]) -> Vec<u8> {
+        let len = args.len() / PACKET_SIZE + 1;
+        let mut data = vec![0; len * PACKET_SIZE];
+        data[0] = (cmd << 3) | len as u8;
+        data[1..(args.len() + 1)].copy_from_slice(args);
+        data
+    }
+
+    fn attr(sgb: &SGB, x: usize, y: usize) -> u8 {
+        sgb.attr_map[y * ATTR_MAP_WIDTH + x]
+    }
+
+    fn rgba(color: u16) -> [u8; COLOR_CHANNELS] {
+        gbc2rgba(color.get_low_byte(), color.get_high_byte())
+    }
+
+    // Inverse of read_vram_data, draws tile data onto the screen
+    fn draw_vram_data(data: &[u8]) -> Vec<u8> {
+        let mut shades = vec![0; SCREEN_WIDTH * SCREEN_HEIGHT];
+        for (i, row_bytes) in data.chunks_exact(2).enumerate() {
+            let tile = i / TILESIZE;
+            let y = (tile / ATTR_MAP_WIDTH) * TILESIZE + i % TILESIZE;
+            let x = (tile % ATTR_MAP_WIDTH) * TILESIZE;
+            for col in 0..TILESIZE {
+                let bit = (TILESIZE - col - 1) as u8;
+                shades[y * SCREEN_WIDTH + x + col] = ((row_bytes[1].get_bit(bit) as u8) << 1) | row_bytes[0].get_bit(bit) as u8;
+            }
+        }
+        shades
+    }
+
+    #[test]
+    fn pal01_colors_screen() {
+        let mut sgb = SGB::new();
+        let colors: [u16; 7] = [0x7FFF, 0x001F, 0x03E0, 0x7C00, 0x1234, 0x2345, 0x3456];
+        let args: Vec<u8> = colors.iter().flat_map(|color| vec![color.get_low_byte(), color.get_high_byte()]).collect();
+        send_command(&mut sgb, &command(PAL01, &args));
+
+        assert_eq!(sgb.palettes[0], [0x7FFF, 0x001F, 0x03E0, 0x7C00]);
+        assert_eq!(sgb.palettes[1], [0x7FFF, 0x1234, 0x2345, 0x3456]);
+        assert_eq!(sgb.palettes[2][0], 0x7FFF);
+
+        // Left half uses palette 0, right half palette 1
+        send_command(&mut sgb, &command(ATTR_DIV, &[0b0000_0001, 10]));
+        let mut shades = [0; SCREEN_WIDTH * SCREEN_HEIGHT];
+        shades[1] = 1;
+        shades[SCREEN_WIDTH - 1] = 3;
+        sgb.update_frame(&shades);
+
+        let screen = sgb.render_screen();
+        let pixel = |i: usize| [screen[4 * i], screen[4 * i + 1], screen[4 * i + 2], screen[4 * i + 3]];
+        assert_eq!(pixel(0), rgba(0x7FFF));
+        assert_eq!(pixel(1), rgba(0x001F));
+        assert_eq!(pixel(SCREEN_WIDTH - 1), rgba(0x3456));
+    }
+
+    #[test]
+    fn packet_without_stop_bit_is_ignored() {
+        let mut sgb = SGB::new();
+        let packet = command(PAL01, &[0xFF, 0x7F]);
+        sgb.write_joypad(0x00);
+        sgb.write_joypad(0x30);
+        for bit in 0..PACKET_BITS {
+            sgb.write_joypad(if packet[bit / 8].get_bit((bit % 8) as u8) { 0x10 } else { 0x20 });
+            sgb.write_joypad(0x30);
+        }
+        // "1" instead of the "0" stop bit
+        sgb.write_joypad(0x10);
+        sgb.write_joypad(0x30);
+        assert_eq!(sgb.palettes[0], DEFAULT_PAL);
+    }
+
+    #[test]
+    fn attr_blk_edges() {
+        // Inside only, the edge follows the inside palette
+        let mut sgb = SGB::new();
+        send_command(&mut sgb, &command(ATTR_BLK, &[1, 0b001, 0b00_00_01, 2, 2, 5, 5]));
+        assert_eq!(attr(&sgb, 3, 3), 1);
+        assert_eq!(attr(&sgb, 2, 2), 1);
+        assert_eq!(attr(&sgb, 5, 4), 1);
+        assert_eq!(attr(&sgb, 6, 3), 0);
+
+        // Outside only, the edge follows the outside palette
+        let mut sgb = SGB::new();
+        send_command(&mut sgb, &command(ATTR_BLK, &[1, 0b100, 0b10_00_00, 2, 2, 5, 5]));
+        assert_eq!(attr(&sgb, 3, 3), 0);
+        assert_eq!(attr(&sgb, 2, 5), 2);
+        assert_eq!(attr(&sgb, 0, 0), 2);
+
+        // Inside and outside without the edge leaves the edge alone
+        let mut sgb = SGB::new();
+        send_command(&mut sgb, &command(ATTR_BLK, &[1, 0b101, 0b10_00_01, 2, 2, 5, 5]));
+        assert_eq!(attr(&sgb, 3, 3), 1);
+        assert_eq!(attr(&sgb, 2, 3), 0);
+        assert_eq!(attr(&sgb, 19, 17), 2);
+
+        // All three set separately
+        let mut sgb = SGB::new();
+        send_command(&mut sgb, &command(ATTR_BLK, &[1, 0b111, 0b10_11_01, 2, 2, 5, 5]));
+        assert_eq!(attr(&sgb, 3, 3), 1);
+        assert_eq!(attr(&sgb, 5, 5), 3);
+        assert_eq!(attr(&sgb, 6, 6), 2);
+    }
+
+    #[test]
+    fn attr_chr_wraps_around() {
+        // Left to right, from the last block back to the first
+        let mut sgb = SGB::new();
+        send_command(&mut sgb, &command(ATTR_CHR, &[19, 17, 2, 0, 0, 0b0110_0000]));
+        assert_eq!(attr(&sgb, 19, 17), 1);
+        assert_eq!(attr(&sgb, 0, 0), 2);
+
+        // Left to right, onto the next row
+        let mut sgb = SGB::new();
+        send_command(&mut sgb, &command(ATTR_CHR, &[19, 3, 2, 0, 0, 0b1111_0000]));
+        assert_eq!(attr(&sgb, 19, 3), 3);
+        assert_eq!(attr(&sgb, 0, 4), 3);
+
+        // Top to bottom, onto the next column
+        let mut sgb = SGB::new();
+        send_command(&mut sgb, &command(ATTR_CHR, &[4, 17, 2, 0, 1, 0b1010_0000]));
+        assert_eq!(attr(&sgb, 4, 17), 2);
+        assert_eq!(attr(&sgb, 5, 0), 2);
+        assert_eq!(attr(&sgb, 4, 0), 0);
+    }
+
+    #[test]
+    fn vram_data_round_trip() {
+        let data: Vec<u8> = (0..TRN_SIZE).map(|i| (i * 7 + i / 256) as u8).collect();
+        assert_eq!(read_vram_data(&draw_vram_data(&data)), data);
+    }
+
+    #[test]
+    fn pal_trn_and_pal_set() {
+        let mut sgb = SGB::new();
+        send_command(&mut sgb, &command(PAL_TRN, &[]));
+
+        let mut data = vec![0; TRN_SIZE];
+        // System palette 3, colors 0-3
+        for (i, color) in [0x1111u16, 0x2222, 0x3333, 0x4444].iter().enumerate() {
+            let addr = 2 * (3 * DMG_PAL_SIZE + i);
+            data[addr] = color.get_low_byte();
+            data[addr + 1] = color.get_high_byte();
+        }
+        sgb.update_frame(&draw_vram_data(&data));
+
+        send_command(&mut sgb, &command(PAL_SET, &[3, 0, 3, 0, 3, 0, 3, 0, 0]));
+        assert_eq!(sgb.palettes[2], [0x1111, 0x2222, 0x3333, 0x4444]);
+    }
+
+    #[test]
+    fn mask_en_black() {
+        let mut sgb = SGB::new();
+        send_command(&mut sgb, &command(MASK_EN, &[2]));
+        sgb.update_frame(&[0; SCREEN_WIDTH * SCREEN_HEIGHT]);
+        assert!(sgb.render_screen().chunks_exact(COLOR_CHANNELS).all(|pixel| pixel == [0, 0, 0, 0xFF]));
+    }
+
+    #[test]
+    fn mlt_req_cycles_players() {
+        let mut sgb = SGB::new();
+        send_command(&mut sgb, &command(MLT_REQ, &[0b01]));
+        assert_eq!(sgb.read_joypad(0xFF) & 0x0F, 0x0F);
+
+        // Reading both button groups moves on to the next controller
+        sgb.write_joypad(0x20);
+        sgb.write_joypad(0x10);
+        sgb.write_joypad(0x30);
+        assert_eq!(sgb.read_joypad(0xFF) & 0x0F, 0x0E);
+    }
+
+    #[test]
+    fn screen_placed_within_border() {
+        let sgb = SGB::new();
+        let screen = [0x80; DISP_SIZE];
+        let output = sgb.render_border(&screen);
+        let pixel = |x: usize, y: usize| output[(y * SGB_SCREEN_WIDTH + x) * COLOR_CHANNELS];
+        assert_eq!(pixel(SCREEN_X, SCREEN_Y), 0x80);
+        assert_eq!(pixel(SCREEN_X + SCREEN_WIDTH - 1, SCREEN_Y + SCREEN_HEIGHT - 1), 0x80);
+        assert_ne!(pixel(SCREEN_X - 1, SCREEN_Y), 0x80);
+    }
+}
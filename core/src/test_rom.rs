@@ -0,0 +1,120 @@
+// Synthetic cartridges for unit tests
+
+use crate::cpu::Cpu;
+use crate::utils::Model;
+
+// =============
+// = Constants =
+// =============
+const ROM_SIZE: usize = 0x8000;
+const ENTRY_ADDR: usize = 0x0100;
+const TITLE_ADDR: usize = 0x0134;
+const CGB_FLAG_ADDR: usize = 0x0143;
+const SGB_FLAG_ADDR: usize = 0x0146;
+const OLD_LICENSEE_ADDR: usize = 0x014B;
+const HEADER_CHECKSUM_ADDR: usize = 0x014D;
+pub const PROGRAM_ADDR: u16 = 0x0150;
+
+/// ```
+/// Build
+///
+/// Creates a 32 KiB ROM-only cartridge which jumps straight to the given program
+///
+/// Input:
+///     Instructions to place at PROGRAM_ADDR (&[u8])
+///
+/// Output:
+///     ROM data (Vec<u8>)
+/// ```
+pub fn build(program: &[u8]) -> Vec<u8> {
+    let mut rom = vec![0; ROM_SIZE];
+    // JP PROGRAM_ADDR
+    rom[ENTRY_ADDR..(ENTRY_ADDR + 4)].copy_from_slice(&[0x00, 0xC3, PROGRAM_ADDR as u8, (PROGRAM_ADDR >> 8) as u8]);
+    let start = PROGRAM_ADDR as usize;
+    rom[start..(start + program.len())].copy_from_slice(program);
+    update_checksum(&mut rom);
+    rom
+}
+
+/// ```
+/// Build SGB
+///
+/// Creates a cartridge as with build, flagged as supporting SGB features
+///
+/// Input:
+///     Instructions to place at PROGRAM_ADDR (&[u8])
+///
+/// Output:
+///     ROM data (Vec<u8>)
+/// ```
+pub fn build_sgb(program: &[u8]) -> Vec<u8> {
+    let mut rom = build(program);
+    rom[SGB_FLAG_ADDR] = 0x03;
+    rom[OLD_LICENSEE_ADDR] = 0x33;
+    update_checksum(&mut rom);
+    rom
+}
+
+/// ```
+/// Build CGB
+///
+/// Creates a cartridge as with build, flagged as supporting CGB features
+///
+/// Input:
+///     Instructions to place at PROGRAM_ADDR (&[u8])
+///
+/// Output:
+///     ROM data (Vec<u8>)
+/// ```
+pub fn build_cgb(program: &[u8]) -> Vec<u8> {
+    let mut rom = build(program);
+    rom[CGB_FLAG_ADDR] = 0x80;
+    update_checksum(&mut rom);
+    rom
+}
+
+/// ```
+/// Set Nintendo title
+///
+/// Gives the cartridge a title and Nintendo's licensee code, as used by the CGB boot ROM to pick a palette
+///
+/// Inputs:
+///     ROM data (&mut [u8])
+///     Title, at most 15 bytes (&[u8])
+/// ```
+pub fn set_nintendo_title(rom: &mut [u8], title: &[u8]) {
+    rom[TITLE_ADDR..(TITLE_ADDR + title.len())].copy_from_slice(title);
+    rom[OLD_LICENSEE_ADDR] = 0x01;
+    update_checksum(rom);
+}
+
+/// ```
+/// Boot
+///
+/// Loads the cartridge into a new system, skipping the boot ROM
+///
+/// Inputs:
+///     ROM data (&[u8])
+///     Hardware to emulate, or None to pick from the cartridge (Option<Model>)
+///
+/// Output:
+///     System, ready to run the cartridge (Cpu)
+/// ```
+pub fn boot(rom: &[u8], model: Option<Model>) -> Cpu {
+    let mut gb = Cpu::new();
+    gb.set_model(model);
+    gb.load_game(rom, false);
+    gb
+}
+
+/// ```
+/// Update checksum
+///
+/// Recalculates the header checksum, as verified by the boot ROM
+///
+/// Input:
+///     ROM data (&mut [u8])
+/// ```
+fn update_checksum(rom: &mut [u8]) {
+    rom[HEADER_CHECKSUM_ADDR] = rom[TITLE_ADDR..HEADER_CHECKSUM_ADDR].iter().fold(0u8, |sum, byte| sum.wrapping_sub(*byte).wrapping_sub(1));
+}
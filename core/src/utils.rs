@@ -6,6 +6,11 @@ pub const SCREEN_HEIGHT: usize = 144;
 pub const COLOR_CHANNELS: usize = 4;
 pub const DISP_SIZE: usize = SCREEN_WIDTH * SCREEN_HEIGHT * COLOR_CHANNELS;
 
+// The Super Game Boy surrounds the screen with a border
+pub const SGB_SCREEN_WIDTH: usize = 256;
+pub const SGB_SCREEN_HEIGHT: usize = 224;
+pub const SGB_DISP_SIZE: usize = SGB_SCREEN_WIDTH * SGB_SCREEN_HEIGHT * COLOR_CHANNELS;
+
 #[allow(non_camel_case_types)]
 #[derive(Copy, Clone, PartialEq, Debug)]
 pub enum GB {
@@ -14,6 +19,73 @@ pub enum GB {
     SGB
 }
 
+/// ```
+/// Model
+///
+/// Specific Game Boy hardware revisions, which games can tell apart by the state the boot ROM leaves behind
+/// ```
+#[allow(clippy::upper_case_acronyms)]
+#[derive(Copy, Clone, PartialEq, Debug)]
+pub enum Model {
+    DMG0,   // Early Japanese DMG
+    DMG,
+    MGB,    // Game Boy Pocket and Light
+    SGB,
+    SGB2,
+    CGB,
+    AGB,    // Game Boy Advance
+}
+
+impl Model {
+    /// ```
+    /// Parse
+    ///
+    /// Finds the model with the given name, ignoring case
+    ///
+    /// Input:
+    ///     Model name, such as "dmg" or "agb" (&str)
+    ///
+    /// Output:
+    ///     Matching model, if any (Option<Model>)
+    /// ```
+    pub fn parse(name: &str) -> Option<Model> {
+        match name.to_ascii_uppercase().as_str() {
+            "DMG0" => { Some(Model::DMG0) },
+            "DMG" =>  { Some(Model::DMG) },
+            "MGB" =>  { Some(Model::MGB) },
+            "SGB" =>  { Some(Model::SGB) },
+            "SGB2" => { Some(Model::SGB2) },
+            "CGB" =>  { Some(Model::CGB) },
+            "AGB" =>  { Some(Model::AGB) },
+            _ =>      { None }
+        }
+    }
+
+    /// ```
+    /// Is CGB
+    ///
+    /// Whether the model has Game Boy Color hardware
+    ///
+    /// Output:
+    ///     Whether CGB games can run in color (bool)
+    /// ```
+    pub fn is_cgb(&self) -> bool {
+        matches!(self, Model::CGB | Model::AGB)
+    }
+
+    /// ```
+    /// Is SGB
+    ///
+    /// Whether the model is a Super Game Boy
+    ///
+    /// Output:
+    ///     Whether the model is an SGB (bool)
+    /// ```
+    pub fn is_sgb(&self) -> bool {
+        matches!(self, Model::SGB | Model::SGB2)
+    }
+}
+
 pub struct Point<T> {
     pub x: T,
     pub y: T,
@@ -0,0 +1,367 @@
+// Songbird SM83 single instruction test runner
+
+/*
+ * Runs JSON test vectors in the format of the SingleStepTests sm83 suite, one file per opcode.
+ * Each file is an array of tests, formatted as such:
+ *
+ * {
+ *     "name": "00 0000",
+ *     "initial": { "pc", "sp", "a", "b", "c", "d", "e", "f", "h", "l", "ime", "ie", "ram": [[addr, val], ...] },
+ *     "final": { same as initial },
+ *     "cycles": [[addr, val, "r-m"], [addr, val, "-wm"], null, ...]
+ * }
+ *
+ * Each entry of "cycles" is one machine cycle, either a read, a write, or an internal cycle.
+ * The instruction is run against a flat 64 KiB RAM, with no hardware attached, logging each access.
+ */
+
+use songbird_core::cpu::{Cpu, Regs};
+use songbird_core::cpu::memory_bus::{BusEvent, FlatBus, InstrumentedBus, MemoryBus};
+use songbird_core::cpu::opcodes;
+
+use serde_json::Value;
+
+use std::cmp::max;
+use std::env;
+use std::fs;
+use std::path::{Path, PathBuf};
+use std::process::exit;
+
+// Constants
+const IE_REG: u16 = 0xFFFF;
+
+// Process exit codes
+const EXIT_PASS: i32 = 0;
+const EXIT_FAIL: i32 = 1;
+const EXIT_USAGE: i32 = 3;
+
+const REGS: [(&str, Regs); 8] = [
+    ("a", Regs::A),
+    ("b", Regs::B),
+    ("c", Regs::C),
+    ("d", Regs::D),
+    ("e", Regs::E),
+    ("f", Regs::F),
+    ("h", Regs::H),
+    ("l", Regs::L),
+];
+
+#[derive(Copy, Clone, Debug, PartialEq)]
+enum Access {
+    Read(u16, u8),
+    Write(u16, u8),
+    Internal,
+}
+
+type TestBus = InstrumentedBus<FlatBus>;
+
+pub fn main() {
+    let args: Vec<_> = env::args().collect();
+    if args.len() < 2 {
+        println!("songbird-sm83 path/to/tests [--verbose]");
+        exit(EXIT_USAGE);
+    }
+    let verbose = args.iter().any(|arg| arg == "--verbose");
+
+    let files = match find_tests(Path::new(&args[1])) {
+        Some(files) => files,
+        None => {
+            println!("No JSON test files found at {}", args[1]);
+            exit(EXIT_USAGE);
+        }
+    };
+
+    let mut total_passed = 0;
+    let mut total = 0;
+    for file in &files {
+        let text = fs::read_to_string(file).expect("Error reading test file");
+        let tests: Value = serde_json::from_str(&text).expect("Error parsing test file");
+        let tests = tests.as_array().expect("Test file should contain an array of tests");
+
+        let mut passed = 0;
+        let mut failed = 0;
+        for test in tests {
+            match run_test(test) {
+                Ok(()) => { passed += 1 },
+                Err(msg) => {
+                    // Only the first failure per file is shown, as an error usually affects every test of an opcode
+                    if verbose || failed == 0 {
+                        println!("{} {}: {}", file.display(), test["name"].as_str().unwrap_or("?"), msg);
+                    }
+                    failed += 1;
+                }
+            }
+        }
+
+        if passed != tests.len() || verbose {
+            println!("{}: {}/{} passed", file.display(), passed, tests.len());
+        }
+        total_passed += passed;
+        total += tests.len();
+    }
+
+    println!("{}/{} tests passed in {} files", total_passed, total, files.len());
+    exit(if total_passed == total { EXIT_PASS } else { EXIT_FAIL });
+}
+
+/// ```
+/// Find tests
+///
+/// Lists the test files at the given path, which can be a single file or a directory of them
+///
+/// Input:
+///     Path to test file or directory (&Path)
+///
+/// Output:
+///     Sorted list of test files, if any (Option<Vec<PathBuf>>)
+/// ```
+fn find_tests(path: &Path) -> Option<Vec<PathBuf>> {
+    let mut files = if path.is_dir() {
+        fs::read_dir(path).ok()?
+            .filter_map(|entry| entry.ok().map(|entry| entry.path()))
+            .filter(|file| file.extension().is_some_and(|ext| ext == "json"))
+            .collect()
+    } else {
+        vec![path.to_path_buf()]
+    };
+
+    files.sort();
+    if files.is_empty() || !files[0].exists() {
+        None
+    } else {
+        Some(files)
+    }
+}
+
+/// ```
+/// Run test
+///
+/// Executes a single instruction test, and compares the results against the expected state
+///
+/// Input:
+///     Test vector (&Value)
+///
+/// Output:
+///     Success, or a description of the first difference (Result<(), String>)
+/// ```
+fn run_test(test: &Value) -> Result<(), String> {
+    let mut cpu = Cpu::with_bus(InstrumentedBus::new(FlatBus::new()));
+    load_state(&mut cpu, &test["initial"])?;
+
+    let cycles = opcodes::execute(&mut cpu);
+    check_state(&cpu, &test["final"])?;
+    check_cycles(&cpu, cycles, &test["cycles"])
+}
+
+/// ```
+/// Load state
+///
+/// Sets up the CPU registers and RAM as specified by the test
+///
+/// Inputs:
+///     CPU under test (&mut Cpu<TestBus>)
+///     Initial state (&Value)
+///
+/// Output:
+///     Success, or a description of the malformed field (Result<(), String>)
+/// ```
+fn load_state(cpu: &mut Cpu<TestBus>, state: &Value) -> Result<(), String> {
+    cpu.set_pc(get_field(state, "pc")? as u16);
+    cpu.set_sp(get_field(state, "sp")? as u16);
+    for (name, reg) in REGS {
+        cpu.set_reg(reg, get_field(state, name)? as u8);
+    }
+    cpu.set_ime(get_field(state, "ime").unwrap_or(0) != 0);
+
+    let bus = cpu.get_bus_mut();
+    if let Ok(ie) = get_field(state, "ie") {
+        bus.poke(IE_REG, ie as u8);
+    }
+    for (addr, val) in get_ram(state)? {
+        bus.poke(addr, val);
+    }
+
+    Ok(())
+}
+
+/// ```
+/// Check state
+///
+/// Compares the CPU registers and RAM against the final state specified by the test
+///
+/// Inputs:
+///     CPU under test (&Cpu<TestBus>)
+///     Expected state (&Value)
+///
+/// Output:
+///     Success, or a description of the first difference (Result<(), String>)
+/// ```
+fn check_state(cpu: &Cpu<TestBus>, state: &Value) -> Result<(), String> {
+    compare("pc", cpu.get_pc() as u64, get_field(state, "pc")?)?;
+    compare("sp", cpu.get_sp() as u64, get_field(state, "sp")?)?;
+    for (name, reg) in REGS {
+        compare(name, cpu.get_reg(reg) as u64, get_field(state, name)?)?;
+    }
+    if let Ok(ime) = get_field(state, "ime") {
+        compare("ime", cpu.get_ime() as u64, ime)?;
+    }
+
+    let bus = cpu.get_bus();
+    for (addr, val) in get_ram(state)? {
+        compare(&format!("${:04X}", addr), bus.peek(addr) as u64, val as u64)?;
+    }
+
+    Ok(())
+}
+
+/// ```
+/// Check cycles
+///
+/// Compares the memory accesses made on each machine cycle against those specified by the test
+///
+/// Inputs:
+///     CPU under test (&Cpu<TestBus>)
+///     Number of machine cycles the instruction reported it took (u8)
+///     Expected cycles (&Value)
+///
+/// Output:
+///     Success, or a description of the first difference (Result<(), String>)
+/// ```
+fn check_cycles(cpu: &Cpu<TestBus>, cycles: u8, expected: &Value) -> Result<(), String> {
+    let expected = expected.as_array().ok_or("Missing field 'cycles'")?;
+
+    // Walk every cycle either side made, so an instruction which runs long or short is reported
+    // at its first wrong access rather than only as a different count
+    // Any cycles left over after the last access are internal ones
+    let recorded = get_accesses(cpu.get_bus().get_log());
+    for i in 0..max(recorded.len(), expected.len()) {
+        let actual = recorded.get(i).copied().unwrap_or(Access::Internal);
+        let wanted = match expected.get(i) {
+            Some(cycle) => { parse_cycle(cycle)? },
+            None => { return Err(format!("cycle {}: got {:?}, expected the instruction to have finished", i, actual)) }
+        };
+        if actual != wanted {
+            return Err(format!("cycle {}: got {:?}, expected {:?}", i, actual, wanted));
+        }
+    }
+
+    compare("cycle count", cycles as u64, expected.len() as u64)
+}
+
+/// ```
+/// Get accesses
+///
+/// Converts the bus log into which access was made on each machine cycle
+/// The CPU ticks the system before each access, so an access belongs to the cycle most recently ticked
+///
+/// Input:
+///     Bus log (&[BusEvent])
+///
+/// Output:
+///     Access made on each machine cycle (Vec<Access>)
+/// ```
+fn get_accesses(log: &[BusEvent]) -> Vec<Access> {
+    let mut accesses = Vec::new();
+    for event in log {
+        let access = match *event {
+            BusEvent::Tick(cycles) => {
+                accesses.extend(std::iter::repeat_n(Access::Internal, cycles as usize));
+                continue;
+            },
+            BusEvent::Read(addr, val) => { Access::Read(addr, val) },
+            BusEvent::Write(addr, val) => { Access::Write(addr, val) },
+        };
+
+        match accesses.last_mut() {
+            Some(cycle) => { *cycle = access },
+            None => { accesses.push(access) }
+        }
+    }
+    accesses
+}
+
+/// ```
+/// Parse cycle
+///
+/// Converts an entry of the test's cycle list to the access it describes
+///
+/// Input:
+///     Cycle entry, null or [addr, val, "rwm"] (&Value)
+///
+/// Output:
+///     Memory access, or a description of the malformed entry (Result<Access, String>)
+/// ```
+fn parse_cycle(cycle: &Value) -> Result<Access, String> {
+    let entry = match cycle.as_array() {
+        Some(entry) if entry.len() == 3 => entry,
+        _ => { return Ok(Access::Internal) }
+    };
+
+    let kind = entry[2].as_str().unwrap_or("");
+    let addr = entry[0].as_u64().ok_or("Malformed cycle address")? as u16;
+    let val = entry[1].as_u64().unwrap_or(0) as u8;
+    if kind.starts_with('r') {
+        Ok(Access::Read(addr, val))
+    } else if kind.get(1..2) == Some("w") {
+        Ok(Access::Write(addr, val))
+    } else {
+        Ok(Access::Internal)
+    }
+}
+
+/// ```
+/// Get field
+///
+/// Reads a numeric field of a test state
+///
+/// Inputs:
+///     Test state (&Value)
+///     Field name (&str)
+///
+/// Output:
+///     Field value, or a description of the missing field (Result<u64, String>)
+/// ```
+fn get_field(state: &Value, name: &str) -> Result<u64, String> {
+    state[name].as_u64().ok_or(format!("Missing field '{}'", name))
+}
+
+/// ```
+/// Get RAM
+///
+/// Reads the RAM contents of a test state
+///
+/// Input:
+///     Test state (&Value)
+///
+/// Output:
+///     List of addresses and values, or a description of the malformed entry (Result<Vec<(u16, u8)>, String>)
+/// ```
+fn get_ram(state: &Value) -> Result<Vec<(u16, u8)>, String> {
+    let entries = state["ram"].as_array().ok_or("Missing field 'ram'")?;
+    entries.iter().map(|entry| {
+        let addr = entry[0].as_u64().ok_or("Malformed RAM address")?;
+        let val = entry[1].as_u64().ok_or("Malformed RAM value")?;
+        Ok((addr as u16, val as u8))
+    }).collect()
+}
+
+/// ```
+/// Compare
+///
+/// Checks a value against what the test expects
+///
+/// Inputs:
+///     Name of value (&str)
+///     Actual value (u64)
+///     Expected value (u64)
+///
+/// Output:
+///     Success, or a description of the difference (Result<(), String>)
+/// ```
+fn compare(name: &str, actual: u64, expected: u64) -> Result<(), String> {
+    if actual == expected {
+        Ok(())
+    } else {
+        Err(format!("{} was ${:02X}, expected ${:02X}", name, actual, expected))
+    }
+}
@@ -0,0 +1,411 @@
+// Songbird headless test ROM runner
+
+use songbird_core::cpu::{Cpu, Regs};
+use songbird_core::ppu::palette::Palette;
+use songbird_core::utils::{Model, COLOR_CHANNELS, DISP_SIZE, SCREEN_HEIGHT, SCREEN_WIDTH};
+
+use std::env;
+use std::fs::File;
+use std::io::{BufWriter, Read};
+use std::process::exit;
+
+// Constants
+const DEFAULT_FRAMES: usize = 60 * 60;
+const LD_B_B: u8 = 0x40;
+
+// Mooneye test ROMs finish by executing LD B,B, with these registers set
+const MOONEYE_PASS: [u8; 6] = [3, 5, 8, 13, 21, 34];
+const MOONEYE_FAIL: [u8; 6] = [0x42; 6];
+
+// Process exit codes
+const EXIT_PASS: i32 = 0;
+const EXIT_FAIL: i32 = 1;
+const EXIT_TIMEOUT: i32 = 2;
+const EXIT_USAGE: i32 = 3;
+
+// Reference screenshots use these shades for the DMG's four colors
+const DMG_SHADES: [u8; 4] = [0xFF, 0xAA, 0x55, 0x00];
+// Colors are compared at the CGB's 5-bit precision, as 8-bit conversions differ between emulators
+const COMPARE_SHIFT: u8 = 3;
+const RGB_CHANNELS: usize = 3;
+
+// FNV-1a parameters, for hashing the screen
+const FNV_OFFSET: u64 = 0xCBF2_9CE4_8422_2325;
+const FNV_PRIME: u64 = 0x0000_0100_0000_01B3;
+
+#[derive(PartialEq)]
+enum Outcome {
+    Pass,
+    Fail,
+    Running,
+}
+
+struct Options {
+    filename: String,
+    frames: usize,
+    force_dmg: bool,
+    serial_pass: Option<String>,
+    serial_fail: Option<String>,
+    mooneye: bool,
+    screen_hash: Option<u64>,
+    reference: Option<String>,
+    diff: Option<String>,
+    boot_rom: Option<String>,
+    model: Option<Model>,
+    strict_vram: bool,
+}
+
+pub fn main() {
+    let opts = match parse_args() {
+        Some(opts) => opts,
+        None => {
+            println!("songbird-test path/to/rom [--frames N] [--dmg] [--serial] [--serial-pass TEXT] [--serial-fail TEXT] [--mooneye] [--hash HEX] [--reference PNG] [--diff PNG] [--boot-rom BIN] [--model DMG0|DMG|MGB|SGB|SGB2|CGB|AGB] [--strict-vram]");
+            exit(EXIT_USAGE);
+        }
+    };
+
+    let mut gb = Cpu::new();
+    gb.set_model(opts.model);
+    gb.set_strict_vram_access(opts.strict_vram);
+    if let Some(path) = &opts.boot_rom {
+        if !gb.load_boot_rom(&load_rom(path)) {
+            println!("{} is not a valid DMG or CGB boot ROM", path);
+            exit(EXIT_USAGE);
+        }
+    }
+    let rom = load_rom(&opts.filename);
+    gb.load_game(&rom, opts.force_dmg);
+    gb.set_serial_capture(opts.serial_pass.is_some() || opts.serial_fail.is_some());
+
+    let mut outcome = Outcome::Running;
+    'frames: for _ in 0..opts.frames {
+        // Run until the end of the frame, watching for the mooneye signature along the way
+        loop {
+            if opts.mooneye && gb.read_ram(gb.get_pc(), None) == LD_B_B {
+                outcome = check_mooneye(&gb);
+                if outcome != Outcome::Running {
+                    break 'frames;
+                }
+            }
+
+            if gb.tick() {
+                break;
+            }
+        }
+
+        outcome = check_serial(&gb, &opts);
+        if outcome != Outcome::Running {
+            break;
+        }
+
+        if let Some(target) = opts.screen_hash {
+            if hash_screen(&gb.render()) == target {
+                outcome = Outcome::Pass;
+                break;
+            }
+        }
+    }
+
+    // Screenshot tests always run for the full number of frames, then compare against the reference
+    if let Some(reference) = &opts.reference {
+        let diff_path = opts.diff.clone().unwrap_or_else(|| format!("{}.diff.png", opts.filename));
+        outcome = compare_reference(&gb.render(), reference, &diff_path);
+    }
+
+    let serial_text = gb.get_serial_text();
+    if !serial_text.is_empty() {
+        println!("{}", serial_text);
+    }
+    println!("Screen hash: {:016x}", hash_screen(&gb.render()));
+
+    match outcome {
+        Outcome::Pass => {
+            println!("PASS");
+            exit(EXIT_PASS);
+        },
+        Outcome::Fail => {
+            println!("FAIL");
+            exit(EXIT_FAIL);
+        },
+        Outcome::Running => {
+            println!("TIMEOUT after {} frames", opts.frames);
+            exit(EXIT_TIMEOUT);
+        }
+    }
+}
+
+/// ```
+/// Parse args
+///
+/// Reads the command line options
+///
+/// Output:
+///     Parsed options, or None if they were invalid (Option<Options>)
+/// ```
+fn parse_args() -> Option<Options> {
+    let args: Vec<_> = env::args().collect();
+    let mut opts = Options {
+        filename: String::new(),
+        frames: DEFAULT_FRAMES,
+        force_dmg: false,
+        serial_pass: None,
+        serial_fail: None,
+        mooneye: false,
+        screen_hash: None,
+        reference: None,
+        diff: None,
+        boot_rom: None,
+        model: None,
+        strict_vram: false,
+    };
+
+    let mut i = 1;
+    while i < args.len() {
+        let value = args.get(i + 1);
+        match args[i].as_str() {
+            "--frames" => {
+                opts.frames = value?.parse().ok()?;
+                i += 1;
+            },
+            "--dmg" => {
+                opts.force_dmg = true;
+            },
+            "--serial" => {
+                // Blargg's test ROMs report their results this way
+                opts.serial_pass = Some("Passed".to_string());
+                opts.serial_fail = Some("Failed".to_string());
+            },
+            "--serial-pass" => {
+                opts.serial_pass = Some(value?.clone());
+                i += 1;
+            },
+            "--serial-fail" => {
+                opts.serial_fail = Some(value?.clone());
+                i += 1;
+            },
+            "--mooneye" => {
+                opts.mooneye = true;
+            },
+            "--hash" => {
+                opts.screen_hash = Some(u64::from_str_radix(value?.trim_start_matches("0x"), 16).ok()?);
+                i += 1;
+            },
+            "--reference" => {
+                opts.reference = Some(value?.clone());
+                i += 1;
+            },
+            "--diff" => {
+                opts.diff = Some(value?.clone());
+                i += 1;
+            },
+            "--boot-rom" => {
+                opts.boot_rom = Some(value?.clone());
+                i += 1;
+            },
+            "--model" => {
+                opts.model = Some(Model::parse(value?)?);
+                i += 1;
+            },
+            "--strict-vram" => {
+                opts.strict_vram = true;
+            },
+            arg => {
+                opts.filename = arg.to_string();
+            }
+        }
+        i += 1;
+    }
+
+    if opts.filename.is_empty() {
+        None
+    } else {
+        Some(opts)
+    }
+}
+
+/// ```
+/// Check mooneye
+///
+/// Checks the registers for the mooneye pass/fail signature, upon reaching LD B,B
+///
+/// Input:
+///     Game Boy CPU (&Cpu)
+///
+/// Output:
+///     Result of the test, if finished (Outcome)
+/// ```
+fn check_mooneye(gb: &Cpu) -> Outcome {
+    let regs = [
+        gb.get_reg(Regs::B),
+        gb.get_reg(Regs::C),
+        gb.get_reg(Regs::D),
+        gb.get_reg(Regs::E),
+        gb.get_reg(Regs::H),
+        gb.get_reg(Regs::L),
+    ];
+
+    if regs == MOONEYE_PASS {
+        Outcome::Pass
+    } else if regs == MOONEYE_FAIL {
+        Outcome::Fail
+    } else {
+        Outcome::Running
+    }
+}
+
+/// ```
+/// Check serial
+///
+/// Checks the text printed over the serial port for the pass or fail message
+///
+/// Inputs:
+///     Game Boy CPU (&Cpu)
+///     Runner options (&Options)
+///
+/// Output:
+///     Result of the test, if finished (Outcome)
+/// ```
+fn check_serial(gb: &Cpu, opts: &Options) -> Outcome {
+    let text = gb.get_serial_text();
+    if opts.serial_fail.as_ref().is_some_and(|fail| text.contains(fail.as_str())) {
+        Outcome::Fail
+    } else if opts.serial_pass.as_ref().is_some_and(|pass| text.contains(pass.as_str())) {
+        Outcome::Pass
+    } else {
+        Outcome::Running
+    }
+}
+
+/// ```
+/// Compare reference
+///
+/// Compares the rendered screen pixel-for-pixel against a reference PNG,
+/// writing an image highlighting any differences if they don't match
+///
+/// Inputs:
+///     Pixel data ([u8])
+///     Path to reference PNG (&str)
+///     Path to write the diff image to (&str)
+///
+/// Output:
+///     Whether the screen matched (Outcome)
+/// ```
+fn compare_reference(data: &[u8; DISP_SIZE], reference: &str, diff_path: &str) -> Outcome {
+    let expected = load_png(reference);
+    let dmg_pal = Palette::new().get_bg_pal();
+
+    let mut mismatches = 0;
+    let mut diff = vec![0; SCREEN_WIDTH * SCREEN_HEIGHT * RGB_CHANNELS];
+    for pixel in 0..(SCREEN_WIDTH * SCREEN_HEIGHT) {
+        let mut actual = [0; RGB_CHANNELS];
+        actual.copy_from_slice(&data[(pixel * COLOR_CHANNELS)..(pixel * COLOR_CHANNELS + RGB_CHANNELS)]);
+
+        // Songbird's DMG shades are darker than the reference ones, so convert them over
+        if let Some(shade) = dmg_pal.iter().position(|color| color[0..RGB_CHANNELS] == actual) {
+            actual = [DMG_SHADES[shade]; RGB_CHANNELS];
+        }
+
+        let start = pixel * RGB_CHANNELS;
+        let wanted = &expected[start..(start + RGB_CHANNELS)];
+        let matches = actual.iter().zip(wanted).all(|(a, b)| (a >> COMPARE_SHIFT) == (b >> COMPARE_SHIFT));
+
+        // Matching pixels are faded out, so the red mismatches stand out
+        if matches {
+            for channel in 0..RGB_CHANNELS {
+                diff[start + channel] = 0x80 + actual[channel] / 2;
+            }
+        } else {
+            mismatches += 1;
+            diff[start..(start + RGB_CHANNELS)].copy_from_slice(&[0xFF, 0x00, 0x00]);
+        }
+    }
+
+    if mismatches == 0 {
+        Outcome::Pass
+    } else {
+        println!("{} pixels differ from {}, see {}", mismatches, reference, diff_path);
+        write_png(diff_path, &diff);
+        Outcome::Fail
+    }
+}
+
+/// ```
+/// Load PNG
+///
+/// Loads a screen sized PNG image, converted to RGB
+///
+/// Input:
+///     Path to image (&str)
+///
+/// Output:
+///     RGB pixel data (Vec<u8>)
+/// ```
+fn load_png(path: &str) -> Vec<u8> {
+    let mut decoder = png::Decoder::new(File::open(path).expect("Error opening reference image"));
+    decoder.set_transformations(png::Transformations::normalize_to_color8());
+    let mut reader = decoder.read_info().expect("Error reading reference image");
+    let mut buffer = vec![0; reader.output_buffer_size()];
+    let info = reader.next_frame(&mut buffer).expect("Error decoding reference image");
+    assert!(info.width as usize == SCREEN_WIDTH && info.height as usize == SCREEN_HEIGHT, "Reference image must be {}x{}", SCREEN_WIDTH, SCREEN_HEIGHT);
+
+    let channels = info.color_type.samples();
+    buffer[..info.buffer_size()].chunks_exact(channels).flat_map(|pixel| {
+        match channels {
+            1 | 2 => [pixel[0]; RGB_CHANNELS],
+            _ => [pixel[0], pixel[1], pixel[2]],
+        }
+    }).collect()
+}
+
+/// ```
+/// Write PNG
+///
+/// Saves screen sized RGB pixel data as a PNG image
+///
+/// Inputs:
+///     Path to image (&str)
+///     RGB pixel data (&[u8])
+/// ```
+fn write_png(path: &str, data: &[u8]) {
+    let file = File::create(path).expect("Error creating diff image");
+    let mut encoder = png::Encoder::new(BufWriter::new(file), SCREEN_WIDTH as u32, SCREEN_HEIGHT as u32);
+    encoder.set_color(png::ColorType::Rgb);
+    encoder.set_depth(png::BitDepth::Eight);
+    let mut writer = encoder.write_header().expect("Error writing diff image");
+    writer.write_image_data(data).expect("Error writing diff image");
+}
+
+/// ```
+/// Hash screen
+///
+/// Computes a stable hash of the rendered screen, to compare against a known good frame
+///
+/// Input:
+///     Pixel data ([u8])
+///
+/// Output:
+///     FNV-1a hash of the pixel data (u64)
+/// ```
+fn hash_screen(data: &[u8; DISP_SIZE]) -> u64 {
+    data.iter().fold(FNV_OFFSET, |hash, &byte| (hash ^ byte as u64).wrapping_mul(FNV_PRIME))
+}
+
+/// ```
+/// Load ROM
+///
+/// Loads game ROM into memory
+///
+/// Input:
+///     Path to game (&str)
+///
+/// Output:
+///     Game data (Vec<u8>)
+/// ```
+fn load_rom(path: &str) -> Vec<u8> {
+    let mut buffer: Vec<u8> = Vec::new();
+
+    let mut f = File::open(path).expect("Error opening ROM");
+    f.read_to_end(&mut buffer).expect("Error reading ROM to buffer");
+
+    buffer
+}
@@ -3,7 +3,9 @@
 
 use songbird_core::cpu::Cpu;
 use songbird_core::io::Buttons;
-use songbird_core::utils::{COLOR_CHANNELS, DISP_SIZE, SCREEN_HEIGHT, SCREEN_WIDTH};
+use songbird_core::serial::printer::Printer;
+use songbird_core::serial::tcp::TcpLink;
+use songbird_core::utils::{Model, COLOR_CHANNELS, SCREEN_HEIGHT, SCREEN_WIDTH, SGB_SCREEN_HEIGHT, SGB_SCREEN_WIDTH};
 
 use sdl2::event::Event;
 use sdl2::keyboard::Keycode;
@@ -19,28 +21,101 @@ use std::io::Read;
 
 // Constants
 const SCALE: u32 = 5;
-const WINDOW_WIDTH: u32 = (SCREEN_WIDTH as u32) * SCALE;
-const WINDOW_HEIGHT: u32 = (SCREEN_HEIGHT as u32) * SCALE;
 
 pub fn main() {
     let args: Vec<_> = env::args().collect();
-    if args.len() == 1 {
-        println!("cargo run path/to/game");
-        return;
+    let mut filename = None;
+    let mut link_host = None;
+    let mut link_connect = None;
+    let mut printer_dir = None;
+    let mut boot_rom = None;
+    let mut model = None;
+    let mut strict_vram = false;
+
+    let mut i = 1;
+    while i < args.len() {
+        match args[i].as_str() {
+            "--link-host" if i + 1 < args.len() => {
+                link_host = Some(args[i + 1].clone());
+                i += 1;
+            },
+            "--link-connect" if i + 1 < args.len() => {
+                link_connect = Some(args[i + 1].clone());
+                i += 1;
+            },
+            "--printer" if i + 1 < args.len() => {
+                printer_dir = Some(args[i + 1].clone());
+                i += 1;
+            },
+            "--boot-rom" if i + 1 < args.len() => {
+                boot_rom = Some(args[i + 1].clone());
+                i += 1;
+            },
+            "--model" if i + 1 < args.len() => {
+                model = Model::parse(&args[i + 1]);
+                if model.is_none() {
+                    println!("Unknown model {}, expected one of DMG0, DMG, MGB, SGB, SGB2, CGB or AGB", args[i + 1]);
+                    return;
+                }
+                i += 1;
+            },
+            "--strict-vram" => {
+                strict_vram = true;
+            },
+            arg => {
+                filename = Some(arg.to_string());
+            }
+        }
+        i += 1;
     }
 
+    let filename = match filename {
+        Some(f) => f,
+        None => {
+            println!("cargo run path/to/game [--boot-rom path/to/boot.bin] [--model name] [--strict-vram] [--link-host address:port | --link-connect address:port | --printer output/dir]");
+            return;
+        }
+    };
+
     // Start game
     let mut gb = Cpu::new();
-    let filename = &args[1];
-    let rom = load_rom(filename);
+    gb.set_model(model);
+    gb.set_strict_vram_access(strict_vram);
+    if let Some(path) = boot_rom {
+        if !gb.load_boot_rom(&load_rom(&path)) {
+            println!("{} is not a valid DMG or CGB boot ROM", path);
+            return;
+        }
+    }
+    let rom = load_rom(&filename);
     gb.load_game(&rom, false);
-    load_battery_save(&mut gb, filename);
+    load_battery_save(&mut gb, &filename);
+
+    // Connect link cable, if requested
+    if let Some(addr) = link_host {
+        println!("Waiting for link cable connection on {}", addr);
+        let link = TcpLink::host(addr.as_str()).expect("Error hosting link cable");
+        gb.set_serial_link(Box::new(link));
+    } else if let Some(addr) = link_connect {
+        let link = TcpLink::connect(addr.as_str()).expect("Error connecting link cable");
+        gb.set_serial_link(Box::new(link));
+    } else if let Some(dir) = printer_dir {
+        gb.set_serial_link(Box::new(Printer::new(dir)));
+    }
     let title = gb.get_title();
 
+    // The Super Game Boy draws a border around the screen
+    let sgb = gb.get_model().is_sgb();
+    let (width, height) = if sgb {
+        (SGB_SCREEN_WIDTH, SGB_SCREEN_HEIGHT)
+    } else {
+        (SCREEN_WIDTH, SCREEN_HEIGHT)
+    };
+
     // Set up SDL
     let sdl_context = sdl2::init().unwrap();
     let video_subsystem = sdl_context.video().unwrap();
-    let window = video_subsystem.window(title, WINDOW_WIDTH, WINDOW_HEIGHT).position_centered().opengl().build().unwrap();
+    let window = video_subsystem.window(title, width as u32 * SCALE, height as u32 * SCALE).position_centered().opengl().build().unwrap();
     let mut canvas = window.into_canvas().present_vsync().build().unwrap();
     canvas.clear();
     canvas.present();
@@ -76,9 +151,12 @@ pub fn main() {
         }
 
         // Game loop
-        tick_until_draw(&mut gb, filename);
-        let disp_arr = gb.render();
-        draw_screen(&disp_arr, &mut canvas);
+        tick_until_draw(&mut gb, &filename);
+        if sgb {
+            draw_screen(&gb.render_sgb(), width, &mut canvas);
+        } else {
+            draw_screen(&gb.render(), width, &mut canvas);
+        }
     }
 }
 
@@ -89,14 +167,15 @@ pub fn main() {
 ///
 /// Inputs:
 ///     Pixel data ([u8])
+///     Width of image in pixels (usize)
 ///     SDL2 Canvas (Canvas<Window>)
 /// ```
-fn draw_screen(data: &[u8; DISP_SIZE], canvas: &mut Canvas<Window>) {
-    for i in (0..DISP_SIZE).step_by(COLOR_CHANNELS) {
+fn draw_screen(data: &[u8], width: usize, canvas: &mut Canvas<Window>) {
+    for i in (0..data.len()).step_by(COLOR_CHANNELS) {
         canvas.set_draw_color(Color::RGB(data[i], data[i + 1], data[i + 2]));
         let pixel = i / COLOR_CHANNELS;
-        let x = (pixel % SCREEN_WIDTH) as u32;
-        let y = (pixel / SCREEN_WIDTH) as u32;
+        let x = (pixel % width) as u32;
+        let y = (pixel / width) as u32;
 
         let rect = Rect::new((x * SCALE) as i32, (y * SCALE) as i32, SCALE, SCALE);
         canvas.fill_rect(rect).unwrap();
@@ -162,6 +241,10 @@ fn tick_until_draw(gb: &mut Cpu, filename: &String) {
     // Keep ticking until returns true, indicating time to render
     while !gb.tick() {}
 
+    if let Some(e) = gb.take_serial_error() {
+        println!("Link cable error: {}", e);
+    }
+
     // Limiting saving battery state to only once per frame.
     // Doing it every tick is both overkill and causes some unknown issue on
     // Windows which traps us in an infinite loop on this frame